@@ -1,4 +1,4 @@
-use crate::*;
+use crate::{*, piece::*};
 use std::fmt;
 use std::str;
 
@@ -9,7 +9,72 @@ pub struct Board {
     castlings: [bool; 4],
     pub en_passant: Option<Coord>,
     halfmove_clock: usize,
-    move_count: usize
+    move_count: usize,
+    hash: u64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FenError {
+    MissingField,
+    InvalidPlacement,
+    InvalidTurn,
+    InvalidCastling,
+    InvalidEnPassant,
+    InvalidCounter,
+    InvalidKings,
+    PawnOnBackRank,
+    OpponentInCheck
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            FenError::MissingField => "wrong number of fields",
+            FenError::InvalidPlacement => "invalid piece placement",
+            FenError::InvalidTurn => "the turn must be b or w",
+            FenError::InvalidCastling => "invalid castling rights",
+            FenError::InvalidEnPassant => "invalid en passant square",
+            FenError::InvalidCounter => "invalid move counter",
+            FenError::InvalidKings => "each side needs exactly one king",
+            FenError::PawnOnBackRank => "pawn on the first or last rank",
+            FenError::OpponentInCheck => "the side not to move is in check"
+        };
+        write!(f, "{}", message)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Undo {
+    pub captured: Option<Piece>,
+    castlings: [bool; 4],
+    en_passant: Option<Coord>,
+    halfmove_clock: usize,
+    hash: u64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ongoing,
+    Checkmate(Color),
+    Stalemate,
+    FiftyMoves,
+    ThreefoldRepetition,
+    InsufficientMaterial
+}
+
+impl Status {
+    pub fn is_over(&self) -> bool {
+        *self != Status::Ongoing
+    }
+
+    pub fn result(&self) -> Option<Result> {
+        match self {
+            Status::Ongoing => None,
+            Status::Checkmate(WHITE) => Some(Result::White),
+            Status::Checkmate(BLACK) => Some(Result::Black),
+            _ => Some(Result::Draw)
+        }
+    }
 }
 
 impl Board {
@@ -20,16 +85,55 @@ impl Board {
             castlings: [true; 4],
             en_passant: None,
             halfmove_clock: 0,
-            move_count: 0
+            move_count: 1,
+            hash: 0
         }
     }
 
     fn set(&mut self, coord: Coord, piece: Option<Piece>) {
         if coord.is_correct() {
+            if let Some(old) = self.position[coord.0 as usize][coord.1 as usize] {
+                self.hash ^= zobrist::piece_key(&old, &coord);
+            }
+            if let Some(new) = piece {
+                self.hash ^= zobrist::piece_key(&new, &coord);
+            }
             self.position[coord.0 as usize][coord.1 as usize] = piece;
         }
     }
 
+    pub(crate) fn capturable_en_passant(&self) -> Option<Coord> {
+        let coord = self.en_passant?;
+        let pawn_dir = Board::pawn_attack_dir(&self.turn);
+        let can_capture = PAWN_CAPTURE_DIRS.iter()
+            .any(|dir| self.is_piece(&(coord+pawn_dir+*dir), &[PAWN], &self.turn));
+        if can_capture {Some(coord)} else {None}
+    }
+
+    fn en_passant_key(&self) -> u64 {
+        self.capturable_en_passant().map_or(0, |coord| zobrist::en_passant_key(&coord))
+    }
+
+    fn compute_hash(&self) -> u64 {
+        let mut hash = zobrist::turn_key(&self.turn) ^ zobrist::castling_key(&self.castlings) ^ self.en_passant_key();
+        for (i, line) in self.position.iter().enumerate() {
+            for (j, piece) in line.iter().enumerate() {
+                if let Some(p) = piece {
+                    hash ^= zobrist::piece_key(p, &Coord(i as isize, j as isize));
+                }
+            }
+        }
+        hash
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    pub(crate) fn castlings(&self) -> [bool; 4] {
+        self.castlings
+    }
+
     fn set_piece(&mut self, coord: Coord, piece: PieceWrapper) {
         if coord.is_correct() {
             self.position[coord.0 as usize][coord.1 as usize] = Some(Piece{piece_type: piece.0, color: piece.1});
@@ -42,115 +146,324 @@ impl Board {
         }
     }
 
-    pub fn default() -> Self {
-        let mut board = Board::new();
-        board.set_piece(Coord(0,0), (ROOK,WHITE));
-        board.set_piece(Coord(0,1), (KNIGHT,WHITE));
-        board.set_piece(Coord(0,2), (BISHOP,WHITE));
-        board.set_piece(Coord(0,3), (QUEEN,WHITE));
-        board.set_piece(Coord(0,4), (KING,WHITE));
-        board.set_piece(Coord(0,5), (BISHOP,WHITE));
-        board.set_piece(Coord(0,6), (KNIGHT,WHITE));
-        board.set_piece(Coord(0,7), (ROOK,WHITE));
-        board.set_piece(Coord(7,0), (ROOK,BLACK));
-        board.set_piece(Coord(7,1), (KNIGHT,BLACK));
-        board.set_piece(Coord(7,2), (BISHOP,BLACK));
-        board.set_piece(Coord(7,3), (QUEEN,BLACK));
-        board.set_piece(Coord(7,4), (KING,BLACK));
-        board.set_piece(Coord(7,5), (BISHOP,BLACK));
-        board.set_piece(Coord(7,6), (KNIGHT,BLACK));
-        board.set_piece(Coord(7,7), (ROOK,BLACK));
-        board.set_line(1, (PAWN,WHITE));
-        board.set_line(6, (PAWN,BLACK));
-        board
-    }
-
-    fn read_rank(&mut self, rank: &[u8], id_rank: isize) {
+    fn read_rank(&mut self, rank: &[u8], id_rank: isize) -> std::result::Result<(), FenError> {
         let mut id_col: isize = 0;
         for c in rank {
             match c {
-                b'/' | b' ' => return,
                 b'1'..=b'8' => id_col += (c-b'0') as isize,
-                c => self.set(Coord(id_rank, id_col), Piece::from_char(&c))
+                c => {
+                    let piece = Piece::from_char(c).ok_or(FenError::InvalidPlacement)?;
+                    if id_col >= 8 {return Err(FenError::InvalidPlacement)}
+                    self.set(Coord(id_rank, id_col), Some(piece));
+                    id_col += 1;
+                }
             }
-            id_col += 1;
         }
+        if id_col != 8 {return Err(FenError::InvalidPlacement)}
+        Ok(())
     }
 
-    fn read_ranks(&mut self, fen: &[u8]) {
-        let ranks = fen.split(|c| *c == b'/');
-        for (id_rank, rank) in ranks.enumerate() {
-            self.read_rank(rank, (7-id_rank) as isize);
+    fn read_ranks(&mut self, fen: &[u8]) -> std::result::Result<(), FenError> {
+        let ranks: Vec<&[u8]> = fen.split(|c| *c == b'/').collect();
+        if ranks.len() != 8 {return Err(FenError::InvalidPlacement)}
+        for (id_rank, rank) in ranks.iter().enumerate() {
+            self.read_rank(rank, (7-id_rank) as isize)?;
         }
+        Ok(())
     }
 
-    fn read_turn(&mut self, fen: &[u8]) {
+    fn read_turn(&mut self, fen: &[u8]) -> std::result::Result<(), FenError> {
         self.turn = match fen {
             [b'b'] => BLACK,
             [b'w'] => WHITE,
-            _ => panic!("The turn must be b or w")
-        }
+            _ => return Err(FenError::InvalidTurn)
+        };
+        Ok(())
     }
 
-    fn read_castlings(&mut self, fen: &[u8]) {
+    fn read_castlings(&mut self, fen: &[u8]) -> std::result::Result<(), FenError> {
         self.castlings = [false; 4];
+        if fen == b"-" {return Ok(())}
         for c in fen {
             match c {
                 b'K' => self.castlings[0] = true,
                 b'Q' => self.castlings[1] = true,
                 b'k' => self.castlings[2] = true,
                 b'q' => self.castlings[3] = true,
-                _ => ()
+                _ => return Err(FenError::InvalidCastling)
             }
         }
+        Ok(())
     }
 
-    fn read_en_passant(&mut self, fen: &[u8]) {
+    fn read_en_passant(&mut self, fen: &[u8]) -> std::result::Result<(), FenError> {
         self.en_passant = match fen {
             [b'-'] => None,
-            s => Coord::from_str(s)
+            s => match (Coord::from_str(s), self.turn) {
+                (Some(coord @ Coord(5, _)), WHITE) | (Some(coord @ Coord(2, _)), BLACK) => Some(coord),
+                _ => return Err(FenError::InvalidEnPassant)
+            }
+        };
+        Ok(())
+    }
+
+    fn read_counter(fen: Option<&[u8]>, default: usize) -> std::result::Result<usize, FenError> {
+        match fen {
+            Some(fen) => str::from_utf8(fen).ok().and_then(|v| v.parse().ok()).ok_or(FenError::InvalidCounter),
+            None => Ok(default)
         }
     }
 
-    fn read_clock(&mut self, fen: &[u8]) {
-        self.halfmove_clock = match str::from_utf8(fen) {
-            Ok(v) => v.parse().unwrap(),
-            _  => unreachable!()  
+    // Rights whose king or rook has left its square are dropped, as many PGN
+    // files keep KQkq in odds games.
+    fn clear_impossible_castlings(&mut self) {
+        let castling_pieces = [(0, 7, WHITE), (0, 0, WHITE), (7, 7, BLACK), (7, 0, BLACK)];
+        for (i, (rank, rook_file, color)) in castling_pieces.into_iter().enumerate() {
+            if !self.is_piece(&Coord(rank, 4), &[KING], &color) || !self.is_piece(&Coord(rank, rook_file), &[ROOK], &color) {
+                self.castlings[i] = false;
+            }
         }
     }
 
-    fn read_move(&mut self, fen: &[u8]) {
-        self.move_count = match str::from_utf8(fen) {
-            Ok(v) => v.parse().unwrap(),
-            _  => unreachable!()  
+    fn validate(&self) -> std::result::Result<(), FenError> {
+        for color in [WHITE, BLACK] {
+            let kings = self.position.iter().flatten()
+                .filter(|p| **p == Some(Piece{piece_type: KING, color})).count();
+            if kings != 1 {return Err(FenError::InvalidKings)}
+        }
+        if self.position[0].iter().chain(self.position[7].iter()).any(|p| matches!(p, Some(Piece{piece_type: PAWN, ..}))) {
+            return Err(FenError::PawnOnBackRank);
+        }
+        if let Some(coord) = self.en_passant {
+            let opponent = change_color(&self.turn);
+            let forward = if opponent == WHITE {1} else {-1};
+            let pushed_pawn = Coord(coord.0 + forward, coord.1);
+            let start = Coord(coord.0 - forward, coord.1);
+            if self.get(&coord).is_some() || self.get(&start).is_some() || !self.is_piece(&pushed_pawn, &[PAWN], &opponent) {
+                return Err(FenError::InvalidEnPassant);
+            }
+        }
+        let opponent_king = self.find_king(&change_color(&self.turn)).unwrap();
+        if self.is_attacked(&opponent_king, &self.turn) {
+            return Err(FenError::OpponentInCheck);
         }
+        Ok(())
     }
 
-    pub fn from_fen(fen: &FEN) -> Self {
+    pub fn try_from_fen(fen: &[u8]) -> std::result::Result<Self, FenError> {
         let mut board = Board::new();
-        let vec: Vec<&[u8]> = fen.split(|c| c.is_ascii_whitespace()).collect();
-        board.read_ranks(vec[0]);
-        board.read_turn(vec[1]);
-        board.read_castlings(vec[2]);
-        board.read_en_passant(vec[3]);
-        board.read_clock(vec[4]);
-        board.read_move(vec[5]);
-        board
+        let vec: Vec<&[u8]> = fen.split(|c| c.is_ascii_whitespace()).filter(|f| !f.is_empty()).collect();
+        if vec.len() < 4 || vec.len() > 6 {return Err(FenError::MissingField)}
+        board.read_ranks(vec[0])?;
+        board.read_turn(vec[1])?;
+        board.read_castlings(vec[2])?;
+        board.read_en_passant(vec[3])?;
+        board.halfmove_clock = Board::read_counter(vec.get(4).copied(), 0)?;
+        board.move_count = Board::read_counter(vec.get(5).copied(), 1)?;
+        board.clear_impossible_castlings();
+        board.validate()?;
+        board.hash = board.compute_hash();
+        Ok(board)
+    }
+
+    pub fn from_fen(fen: &FEN) -> Self {
+        match Board::try_from_fen(fen) {
+            Ok(board) => board,
+            Err(e) => panic!("Invalid FEN: {}", e)
+        }
+    }
+
+    pub fn turn(&self) -> Color {
+        self.turn
+    }
+
+    pub fn move_count(&self) -> usize {
+        self.move_count
+    }
+
+    pub fn halfmove_clock(&self) -> usize {
+        self.halfmove_clock
+    }
+
+    pub fn get(&self, coord: &Coord) -> Option<Piece> {
+        if !coord.is_correct() {return None}
+        self.position[coord.0 as usize][coord.1 as usize]
+    }
+
+    fn find_king(&self, color: &Color) -> Option<Coord> {
+        for (i, line) in self.position.iter().enumerate() {
+            for (j, piece) in line.iter().enumerate() {
+                if *piece == Some(Piece{piece_type: KING, color: *color}) {
+                    return Some(Coord(i as isize, j as isize));
+                }
+            }
+        }
+        None
+    }
+
+    fn is_piece(&self, coord: &Coord, piece_types: &[PieceType], color: &Color) -> bool {
+        match self.get(coord) {
+            Some(p) => p.color == *color && piece_types.contains(&p.piece_type),
+            None => false
+        }
+    }
+
+    fn slider_on_ray(&self, coord: &Coord, dir: &Coord, piece_types: &[PieceType], color: &Color) -> Option<Coord> {
+        for nb_squares in 1..8 {
+            let square = *coord+*dir*nb_squares;
+            if !square.is_correct() {return None}
+            if !self.is_square_free(&square) {
+                return if self.is_piece(&square, piece_types, color) {Some(square)} else {None};
+            }
+        }
+        None
+    }
+
+    fn pawn_attack_dir(by: &Color) -> Coord {
+        match by {
+            Color::WHITE => Coord(-1, 0),
+            Color::BLACK => Coord(1, 0)
+        }
+    }
+
+    pub fn is_attacked(&self, coord: &Coord, by: &Color) -> bool {
+        let pawn_dir = Board::pawn_attack_dir(by);
+        PAWN_CAPTURE_DIRS.iter().any(|dir| self.is_piece(&(*coord+pawn_dir+*dir), &[PAWN], by))
+            || KNIGHT_DIRS.iter().any(|dir| self.is_piece(&(*coord+*dir), &[KNIGHT], by))
+            || QUEEN_DIRS.iter().any(|dir| self.is_piece(&(*coord+*dir), &[KING], by))
+            || ROOK_DIRS.iter().any(|dir| self.slider_on_ray(coord, dir, &[ROOK, QUEEN], by).is_some())
+            || BISHOP_DIRS.iter().any(|dir| self.slider_on_ray(coord, dir, &[BISHOP, QUEEN], by).is_some())
+    }
+
+    pub fn attackers_of(&self, coord: &Coord, by: &Color) -> Vec<Coord> {
+        let pawn_dir = Board::pawn_attack_dir(by);
+        let mut attackers = Vec::<Coord>::new();
+        let steps = [(&PAWN_CAPTURE_DIRS.map(|dir| dir+pawn_dir)[..], PAWN), (&KNIGHT_DIRS[..], KNIGHT), (&QUEEN_DIRS[..], KING)];
+        for (dirs, piece_type) in steps {
+            attackers.extend(dirs.iter().map(|dir| *coord+*dir).filter(|square| self.is_piece(square, &[piece_type], by)));
+        }
+        attackers.extend(ROOK_DIRS.iter().filter_map(|dir| self.slider_on_ray(coord, dir, &[ROOK, QUEEN], by)));
+        attackers.extend(BISHOP_DIRS.iter().filter_map(|dir| self.slider_on_ray(coord, dir, &[BISHOP, QUEEN], by)));
+        attackers
     }
 
-    // pub fn available_moves(&self) -> Vec<ChessMove> {
-    //     let mut av_moves = Vec::<ChessMove>::new();
-    //     for (i, line) in self.position.iter().enumerate() {
-    //         for (j, piece) in line.iter().enumerate() {
-    //             if let Some(p) = piece {
-    //                 if p.color == self.turn {
-    //                     av_moves.append(&mut p.available_moves(&self, &Coord(i as isize,j as isize)));
-    //                 }
-    //             }
-    //         }
-    //     }
-    //     av_moves
-    // }
+    pub fn is_in_check(&self) -> bool {
+        match self.find_king(&self.turn) {
+            Some(king) => self.is_attacked(&king, &change_color(&self.turn)),
+            None => false
+        }
+    }
+
+    pub(crate) fn castling_moves(&self, coord: &Coord, color: &Color) -> Vec<ChessMove> {
+        let mut moves = Vec::<ChessMove>::new();
+        let (rank, first_castling) = match color {
+            Color::WHITE => (0, 0),
+            Color::BLACK => (7, 2)
+        };
+        if *coord != Coord(rank, 4) {return moves}
+        let opponent = change_color(color);
+        let king = Piece{piece_type: KING, color: *color};
+        // (castling index, rook file, squares to be empty, squares not to be attacked)
+        let sides: [(usize, isize, &[isize], &[isize]); 2] = [
+            (first_castling, 7, &[5, 6], &[4, 5, 6]),
+            (first_castling+1, 0, &[1, 2, 3], &[4, 3, 2])
+        ];
+        for (castling, rook_file, empty, safe) in sides {
+            if !self.castlings[castling] || !self.is_piece(&Coord(rank, rook_file), &[ROOK], color) {continue}
+            if !empty.iter().all(|file| self.is_square_free(&Coord(rank, *file))) {continue}
+            if safe.iter().any(|file| self.is_attacked(&Coord(rank, *file), &opponent)) {continue}
+            let end = if rook_file == 7 {Coord(rank, 6)} else {Coord(rank, 2)};
+            let mut chess_move = ChessMove::new(king, *coord, end);
+            chess_move.is_king_castling = rook_file == 7;
+            chess_move.is_queen_castling = rook_file == 0;
+            moves.push(chess_move);
+        }
+        moves
+    }
+
+    pub(crate) fn available_moves(&self) -> Vec<ChessMove> {
+        let mut av_moves = Vec::<ChessMove>::new();
+        for (i, line) in self.position.iter().enumerate() {
+            for (j, piece) in line.iter().enumerate() {
+                if let Some(p) = piece {
+                    if p.color == self.turn {
+                        av_moves.append(&mut p.available_moves(self, &Coord(i as isize,j as isize)));
+                    }
+                }
+            }
+        }
+        av_moves
+    }
+
+    pub fn is_legal(&self, chess_move: &ChessMove) -> bool {
+        let mut board = *self;
+        board.move_pieces(chess_move);
+        match board.find_king(&self.turn) {
+            Some(king) => !board.is_attacked(&king, &change_color(&self.turn)),
+            None => true
+        }
+    }
+
+    pub(crate) fn has_legal_moves(&self) -> bool {
+        self.available_moves().iter().any(|m| self.is_legal(m))
+    }
+
+    pub(crate) fn annotate(&self, chess_move: &mut ChessMove) {
+        let mut board = *self;
+        board.do_move(chess_move);
+        chess_move.is_check = board.is_in_check();
+        chess_move.is_mate = chess_move.is_check && !board.has_legal_moves();
+    }
+
+    pub fn legal_moves(&self) -> Vec<ChessMove> {
+        let mut moves: Vec<ChessMove> = self.available_moves().into_iter().filter(|m| self.is_legal(m)).collect();
+        for chess_move in moves.iter_mut() {
+            self.annotate(chess_move);
+        }
+        moves
+    }
+
+    fn is_insufficient_material(&self) -> bool {
+        let mut minors = Vec::<(PieceType, isize)>::new();
+        for (i, line) in self.position.iter().enumerate() {
+            for (j, piece) in line.iter().enumerate() {
+                match piece {
+                    Some(Piece{piece_type: KING, ..}) | None => (),
+                    Some(Piece{piece_type: p @ (KNIGHT | BISHOP), ..}) => minors.push((*p, ((i+j)%2) as isize)),
+                    Some(_) => return false
+                }
+            }
+        }
+        match minors[..] {
+            [] | [_] => true,
+            _ => minors.iter().all(|(p, square_color)| *p == BISHOP && *square_color == minors[0].1)
+        }
+    }
+
+    fn is_same_position(&self, other: &Board) -> bool {
+        self.hash == other.hash && self.position == other.position && self.turn == other.turn
+            && self.castlings == other.castlings
+    }
+
+    pub fn status(&self) -> Status {
+        self.status_with_history(&[])
+    }
+
+    pub fn status_with_history(&self, history: &[Board]) -> Status {
+        if !self.has_legal_moves() {
+            return if self.is_in_check() {Status::Checkmate(change_color(&self.turn))} else {Status::Stalemate};
+        }
+        if self.halfmove_clock >= 100 {
+            return Status::FiftyMoves;
+        }
+        if history.iter().filter(|board| board.is_same_position(self)).count() >= 2 {
+            return Status::ThreefoldRepetition;
+        }
+        if self.is_insufficient_material() {
+            return Status::InsufficientMaterial;
+        }
+        Status::Ongoing
+    }
 
     pub fn is_square_free(&self, coord: &Coord) -> bool {
         if !coord.is_correct() {return false}
@@ -173,18 +486,119 @@ impl Board {
         }
     }
 
-    pub fn do_move(&mut self, chess_move: &ChessMove) {
+    fn move_pieces(&mut self, chess_move: &ChessMove) {
+        let piece = chess_move.promotion.unwrap_or(chess_move.piece);
+        if chess_move.piece.piece_type == PAWN && Some(chess_move.end) == self.en_passant
+            && chess_move.start.1 != chess_move.end.1 {
+            self.set(Coord(chess_move.start.0, chess_move.end.1), None);
+        }
+        if chess_move.piece.piece_type == KING && (chess_move.end.1 - chess_move.start.1).abs() == 2 {
+            let rank = chess_move.start.0;
+            let (rook_start, rook_end) = if chess_move.end.1 > chess_move.start.1 {(7, 5)} else {(0, 3)};
+            let rook = self.get(&Coord(rank, rook_start));
+            self.set(Coord(rank, rook_start), None);
+            self.set(Coord(rank, rook_end), rook);
+        }
         self.set(chess_move.start, None);
-        let move_is_capture = !self.is_square_free(&chess_move.end);
-        self.set(chess_move.end, Some(chess_move.piece));
+        self.set(chess_move.end, Some(piece));
+    }
+
+    fn update_castlings(&mut self, square: &Coord) {
+        let lost: &[usize] = match square {
+            Coord(0, 4) => &[0, 1],
+            Coord(0, 7) => &[0],
+            Coord(0, 0) => &[1],
+            Coord(7, 4) => &[2, 3],
+            Coord(7, 7) => &[2],
+            Coord(7, 0) => &[3],
+            _ => &[]
+        };
+        for castling in lost {
+            self.castlings[*castling] = false;
+        }
+    }
+
+    pub fn make_move(&mut self, chess_move: &ChessMove) -> Undo {
+        let is_pawn = chess_move.piece.piece_type == PAWN;
+        let is_en_passant = is_pawn && Some(chess_move.end) == self.en_passant
+            && chess_move.start.1 != chess_move.end.1;
+        let captured = if is_en_passant {
+            self.get(&Coord(chess_move.start.0, chess_move.end.1))
+        }
+        else {
+            self.get(&chess_move.end)
+        };
+        let undo = Undo {
+            captured,
+            castlings: self.castlings,
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock,
+            hash: self.hash
+        };
+        self.hash ^= self.en_passant_key() ^ zobrist::castling_key(&self.castlings);
+        self.move_pieces(chess_move);
+        self.update_castlings(&chess_move.start);
+        self.update_castlings(&chess_move.end);
+        self.en_passant = if is_pawn && (chess_move.end.0 - chess_move.start.0).abs() == 2 {
+            Some(Coord((chess_move.start.0 + chess_move.end.0)/2, chess_move.start.1))
+        }
+        else {
+            None
+        };
         self.turn = change_color(&self.turn);
+        self.hash ^= zobrist::turn_key(&BLACK) ^ self.en_passant_key() ^ zobrist::castling_key(&self.castlings);
         self.halfmove_clock += 1;
         if self.turn == Color::WHITE {
             self.move_count += 1;
         }
-        if chess_move.piece.piece_type == PAWN || move_is_capture {
+        if is_pawn || captured.is_some() {
             self.halfmove_clock = 0;
         }
+        undo
+    }
+
+    pub fn unmake_move(&mut self, chess_move: &ChessMove, undo: &Undo) {
+        if self.turn == Color::WHITE {
+            self.move_count -= 1;
+        }
+        self.turn = change_color(&self.turn);
+        self.castlings = undo.castlings;
+        self.en_passant = undo.en_passant;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.set(chess_move.start, Some(chess_move.piece));
+        if chess_move.piece.piece_type == PAWN && Some(chess_move.end) == self.en_passant
+            && chess_move.start.1 != chess_move.end.1 {
+            self.set(chess_move.end, None);
+            self.set(Coord(chess_move.start.0, chess_move.end.1), undo.captured);
+        }
+        else {
+            self.set(chess_move.end, undo.captured);
+        }
+        if chess_move.piece.piece_type == KING && (chess_move.end.1 - chess_move.start.1).abs() == 2 {
+            let rank = chess_move.start.0;
+            let (rook_start, rook_end) = if chess_move.end.1 > chess_move.start.1 {(7, 5)} else {(0, 3)};
+            let rook = self.get(&Coord(rank, rook_end));
+            self.set(Coord(rank, rook_end), None);
+            self.set(Coord(rank, rook_start), rook);
+        }
+        self.hash = undo.hash;
+    }
+
+    pub fn do_move(&mut self, chess_move: &ChessMove) {
+        self.make_move(chess_move);
+    }
+
+    pub fn perft(&mut self, depth: usize) -> u64 {
+        if depth == 0 {return 1}
+        let moves: Vec<ChessMove> = self.available_moves().into_iter().filter(|m| self.is_legal(m)).collect();
+        if depth == 1 {return moves.len() as u64}
+        let mut nodes = 0;
+        for chess_move in moves {
+            let undo = self.make_move(&chess_move);
+            nodes += self.perft(depth-1);
+            self.unmake_move(&chess_move, &undo);
+        }
+        nodes
     }
 
     fn write_rank(&self, id_rank: usize) -> Vec<u8> {
@@ -272,6 +686,32 @@ impl Board {
     }
 }
 
+impl Default for Board {
+    fn default() -> Self {
+        let mut board = Board::new();
+        board.set_piece(Coord(0,0), (ROOK,WHITE));
+        board.set_piece(Coord(0,1), (KNIGHT,WHITE));
+        board.set_piece(Coord(0,2), (BISHOP,WHITE));
+        board.set_piece(Coord(0,3), (QUEEN,WHITE));
+        board.set_piece(Coord(0,4), (KING,WHITE));
+        board.set_piece(Coord(0,5), (BISHOP,WHITE));
+        board.set_piece(Coord(0,6), (KNIGHT,WHITE));
+        board.set_piece(Coord(0,7), (ROOK,WHITE));
+        board.set_piece(Coord(7,0), (ROOK,BLACK));
+        board.set_piece(Coord(7,1), (KNIGHT,BLACK));
+        board.set_piece(Coord(7,2), (BISHOP,BLACK));
+        board.set_piece(Coord(7,3), (QUEEN,BLACK));
+        board.set_piece(Coord(7,4), (KING,BLACK));
+        board.set_piece(Coord(7,5), (BISHOP,BLACK));
+        board.set_piece(Coord(7,6), (KNIGHT,BLACK));
+        board.set_piece(Coord(7,7), (ROOK,BLACK));
+        board.set_line(1, (PAWN,WHITE));
+        board.set_line(6, (PAWN,BLACK));
+        board.hash = board.compute_hash();
+        board
+    }
+}
+
 impl fmt::Display for Board {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for line in self.position {
@@ -285,9 +725,144 @@ impl fmt::Display for Board {
                 }
             }
             disp.push('\n');
-            let res = write!(f, "{}", disp);
-            if res.is_err() {return res}
+            write!(f, "{}", disp)?;
         }   
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KIWIPETE: &[u8] = b"r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+    const POSITION_3: &[u8] = b"8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+    const POSITION_4: &[u8] = b"r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+    const POSITION_5: &[u8] = b"rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+    const POSITION_6: &[u8] = b"r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10";
+
+    fn board(fen: &[u8]) -> Board {
+        Board::try_from_fen(fen).unwrap()
+    }
+
+    fn check_make_unmake(board: &mut Board, depth: usize) {
+        if depth == 0 {return}
+        for chess_move in board.legal_moves() {
+            let fen = board.to_fen();
+            let hash = board.hash();
+            let undo = board.make_move(&chess_move);
+            assert_eq!(board.hash(), board.compute_hash(), "{}", String::from_utf8_lossy(&board.to_fen()));
+            check_make_unmake(board, depth - 1);
+            board.unmake_move(&chess_move, &undo);
+            assert_eq!(board.to_fen(), fen);
+            assert_eq!(board.hash(), hash);
+        }
+    }
+
+    #[test]
+    fn perft_start_position() {
+        let mut board = Board::default();
+        assert_eq!([1, 2, 3, 4].map(|depth| board.perft(depth)), [20, 400, 8902, 197281]);
+    }
+
+    #[test]
+    fn perft_kiwipete() {
+        assert_eq!([1, 2, 3].map(|depth| board(KIWIPETE).perft(depth)), [48, 2039, 97862]);
+    }
+
+    #[test]
+    fn perft_position_3() {
+        assert_eq!([1, 2, 3, 4].map(|depth| board(POSITION_3).perft(depth)), [14, 191, 2812, 43238]);
+    }
+
+    #[test]
+    fn perft_position_4() {
+        assert_eq!([1, 2, 3].map(|depth| board(POSITION_4).perft(depth)), [6, 264, 9467]);
+    }
+
+    #[test]
+    fn perft_position_5() {
+        assert_eq!([1, 2, 3].map(|depth| board(POSITION_5).perft(depth)), [44, 1486, 62379]);
+    }
+
+    #[test]
+    fn perft_position_6() {
+        assert_eq!([1, 2, 3].map(|depth| board(POSITION_6).perft(depth)), [46, 2079, 89890]);
+    }
+
+    #[test]
+    fn fen_drops_impossible_rights() {
+        let fen = |fen: &[u8]| Board::try_from_fen(fen).map(|board| board.to_fen());
+        assert_eq!(fen(b"k7/8/8/8/8/8/8/K7 w KQ - 0 1"), Ok(b"k7/8/8/8/8/8/8/K7 w - - 0 1".to_vec()));
+        assert_eq!(fen(b"r3k3/8/8/8/8/8/8/4K2R w KQkq - 0 1"), Ok(b"r3k3/8/8/8/8/8/8/4K2R w Kq - 0 1".to_vec()));
+        assert_eq!(Board::try_from_fen(b"k7/8/8/8/8/8/8/K7 w KQ - 0 1").unwrap().hash(),
+            Board::try_from_fen(b"k7/8/8/8/8/8/8/K7 w - - 0 1").unwrap().hash());
+        assert_eq!(Board::try_from_fen(b"4k3/8/8/8/8/8/8/4K3 w - e6 0 1").unwrap_err(), FenError::InvalidEnPassant);
+        assert_eq!(Board::try_from_fen(b"4k3/4n3/8/4p3/8/8/8/4K3 w - e6 0 1").unwrap_err(), FenError::InvalidEnPassant);
+        assert!(Board::try_from_fen(b"4k3/8/8/4p3/8/8/8/4K3 w - e6 0 1").is_ok());
+        assert!(Board::try_from_fen(b"r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1").is_ok());
+    }
+
+    #[test]
+    fn make_unmake_restores_position_and_hash() {
+        for fen in [KIWIPETE, POSITION_3, POSITION_4, POSITION_5, POSITION_6] {
+            check_make_unmake(&mut board(fen), 2);
+        }
+        check_make_unmake(&mut Board::default(), 3);
+    }
+
+    #[test]
+    fn status_of_finished_games() {
+        let mated = board(b"rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3");
+        assert!(mated.is_in_check());
+        assert_eq!(mated.status(), Status::Checkmate(BLACK));
+        assert_eq!(mated.status().result(), Some(Result::Black));
+        let before = board(b"rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq g3 0 2");
+        let mate = ChessMove::from_notation(b"Qh4", &before).unwrap();
+        assert!(mate.is_check && mate.is_mate);
+
+        let stalemate = board(b"7k/5Q2/6K1/8/8/8/8/8 b - - 0 1");
+        assert!(!stalemate.is_in_check());
+        assert_eq!(stalemate.status(), Status::Stalemate);
+        assert_eq!(stalemate.status().result(), Some(Result::Draw));
+        assert_eq!(board(b"4k3/8/8/8/8/8/8/R3K3 w - - 100 80").status(), Status::FiftyMoves);
+        assert_eq!(Board::default().status(), Status::Ongoing);
+        assert!(!Board::default().status().is_over());
+    }
+
+    #[test]
+    fn insufficient_material() {
+        let status = |fen: &[u8]| board(fen).status();
+        assert_eq!(status(b"4k3/8/8/8/8/8/8/4K3 w - - 0 1"), Status::InsufficientMaterial);
+        assert_eq!(status(b"4k3/8/8/8/8/8/8/4KN2 w - - 0 1"), Status::InsufficientMaterial);
+        assert_eq!(status(b"4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1"), Status::InsufficientMaterial);
+        assert_eq!(status(b"2b1k3/8/8/8/8/8/8/2B1K3 w - - 0 1"), Status::Ongoing);
+        assert_eq!(status(b"4k3/8/8/8/8/8/8/3NKN2 w - - 0 1"), Status::Ongoing);
+        assert_eq!(status(b"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"), Status::Ongoing);
+    }
+
+    #[test]
+    fn threefold_repetition() {
+        let mut board = Board::default();
+        let mut history = vec![];
+        for (i, notation) in ["Nf3", "Nf6", "Ng1", "Ng8", "Nf3", "Nf6", "Ng1", "Ng8"].iter().enumerate() {
+            assert_eq!(board.status_with_history(&history), Status::Ongoing, "after {} plies", i);
+            let chess_move = ChessMove::from_notation(notation.as_bytes(), &board).unwrap();
+            history.push(board);
+            board.do_move(&chess_move);
+        }
+        assert_eq!(board.status_with_history(&history), Status::ThreefoldRepetition);
+    }
+
+    #[test]
+    fn attackers() {
+        let board = board(KIWIPETE);
+        let square = |name: &[u8]| Coord::from_str(name).unwrap();
+        let mut attackers = board.attackers_of(&square(b"f6"), &WHITE);
+        attackers.sort_by_key(|coord| (coord.0, coord.1));
+        assert_eq!(attackers, vec![square(b"f3")]);
+        let mut attackers = board.attackers_of(&square(b"d5"), &BLACK);
+        attackers.sort_by_key(|coord| (coord.0, coord.1));
+        assert_eq!(attackers, vec![square(b"b6"), square(b"e6"), square(b"f6")]);
+        assert!(!board.is_in_check());
+    }
+}
@@ -1,6 +1,6 @@
 use crate::{*, piece::PieceType};
 use serde::{Serialize, Deserialize};
-use std::str;
+use std::fmt;
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ChessMove {
@@ -15,40 +15,177 @@ pub struct ChessMove {
     pub promotion: Option<Piece>
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotationError {
+    InvalidSyntax,
+    IllegalMove,
+    AmbiguousMove
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotationError::InvalidSyntax => write!(f, "invalid move notation"),
+            NotationError::IllegalMove => write!(f, "illegal move"),
+            NotationError::AmbiguousMove => write!(f, "ambiguous move")
+        }
+    }
+}
+
 impl ChessMove {
-    // pub fn new(piece: Piece, start: &str, end: &str) -> Self {
-    //     ChessMove {
-    //         piece,
-    //         start: from_str_to_coord(start).unwrap(), 
-    //         end: from_str_to_coord(end).unwrap()
-    //     }
-    // }
+    pub fn new(piece: Piece, start: Coord, end: Coord) -> Self {
+        ChessMove {
+            piece,
+            start,
+            end,
+            ..ChessMove::default()
+        }
+    }
 
-    pub fn notation(&self) -> Vec<u8> {
+    pub fn notation(&self, board: &Board) -> Vec<u8> {
         let mut res = vec![];
-        if let Some(c) = self.piece.get_notation() {
-            res.push(c);
+        if self.is_king_castling {
+            res.extend(b"O-O");
+        }
+        else if self.is_queen_castling {
+            res.extend(b"O-O-O");
+        }
+        else {
+            let is_capture = !board.is_square_free(&self.end)
+                || self.piece.piece_type == PAWN && self.start.1 != self.end.1;
+            match self.piece.get_notation() {
+                Some(c) => {
+                    res.push(c);
+                    let rivals: Vec<ChessMove> = board.available_moves().into_iter()
+                        .filter(|m| m.piece == self.piece && m.end == self.end && m.start != self.start && board.is_legal(m))
+                        .collect();
+                    if !rivals.is_empty() {
+                        if rivals.iter().all(|m| m.start.1 != self.start.1) {
+                            res.push(self.start.get_char_column());
+                        }
+                        else if rivals.iter().all(|m| m.start.0 != self.start.0) {
+                            res.push(self.start.get_char_line());
+                        }
+                        else {
+                            res.extend(self.start.get_str());
+                        }
+                    }
+                }
+                None if is_capture => res.push(self.start.get_char_column()),
+                None => ()
+            }
+            if is_capture {
+                res.push(b'x');
+            }
+            res.extend(self.end.get_str());
+            if let Some(p) = self.promotion {
+                res.push(b'=');
+                res.push(p.piece_type.get_char());
+            }
+        }
+        let mut after = *board;
+        after.do_move(self);
+        if after.is_in_check() {
+            res.push(if after.has_legal_moves() {b'+'} else {b'#'});
         }
-        res.extend(self.end.get_str());
         res
     }
 
-    pub fn from_notation(notation: &[u8], turn: Color) -> Self {
-        let mut chess_move = ChessMove::default();
-        if &notation[..3] == b"O-O" {
-            chess_move.piece = Piece{piece_type: KING, color: turn};
-            chess_move.is_king_castling = true;
+    pub fn from_notation(notation: &[u8], board: &Board) -> std::result::Result<Self, NotationError> {
+        let end_of_move = notation.iter()
+            .rposition(|c| !matches!(c, b'+' | b'#' | b'!' | b'?' | b' '))
+            .ok_or(NotationError::InvalidSyntax)?;
+        let notation = &notation[..=end_of_move];
+        let moves = board.available_moves();
+        let candidates: Vec<&ChessMove> = match notation {
+            b"O-O" | b"0-0" => moves.iter().filter(|m| m.is_king_castling).collect(),
+            b"O-O-O" | b"0-0-0" => moves.iter().filter(|m| m.is_queen_castling).collect(),
+            _ => {
+                let (piece_type, notation) = match PieceType::from_uppercase(&notation[0]) {
+                    Some(p) => (p, &notation[1..]),
+                    None => (PAWN, notation)
+                };
+                let (promotion, notation) = match notation {
+                    [rest @ .., b'=', p] => (Some(PieceType::from_char(p).ok_or(NotationError::InvalidSyntax)?), rest),
+                    [rest @ .., p] if piece_type == PAWN && p.is_ascii_uppercase() =>
+                        (Some(PieceType::from_uppercase(p).ok_or(NotationError::InvalidSyntax)?), rest),
+                    _ => (None, notation)
+                };
+                let squares: Vec<u8> = notation.iter().filter(|c| !matches!(c, b'x' | b':' | b'-')).copied().collect();
+                if squares.len() < 2 || squares.len() > 4 {
+                    return Err(NotationError::InvalidSyntax);
+                }
+                let (from, to) = squares.split_at(squares.len()-2);
+                let end = Coord::from_str(to).ok_or(NotationError::InvalidSyntax)?;
+                let mut from_file = None;
+                let mut from_rank = None;
+                for c in from {
+                    match c {
+                        b'a'..=b'h' => from_file = Some((c-b'a') as isize),
+                        b'1'..=b'8' => from_rank = Some((c-b'1') as isize),
+                        _ => return Err(NotationError::InvalidSyntax)
+                    }
+                }
+                moves.iter().filter(|m| {
+                    m.piece.piece_type == piece_type && m.end == end
+                        && m.promotion.map(|p| p.piece_type) == promotion
+                        && from_file.is_none_or(|file| m.start.1 == file)
+                        && from_rank.is_none_or(|rank| m.start.0 == rank)
+                        && board.is_legal(m)
+                }).collect()
+            }
+        };
+        match candidates[..] {
+            [chess_move] => {
+                let mut chess_move = *chess_move;
+                board.annotate(&mut chess_move);
+                Ok(chess_move)
+            }
+            [] => Err(NotationError::IllegalMove),
+            _ => Err(NotationError::AmbiguousMove)
         }
-        if &notation[..5] == b"O-O-O" {
-            chess_move.piece = Piece{piece_type: KING, color: turn};
-            chess_move.is_queen_castling = true;
+    }
+
+    pub fn uci(&self) -> Vec<u8> {
+        let mut res = self.start.get_str();
+        res.extend(self.end.get_str());
+        if let Some(p) = self.promotion {
+            res.push(p.piece_type.get_char().to_ascii_lowercase());
         }
-        let piece_type = match PieceType::from_char(&notation[0]) {
-            Some(p) => p,
-            _ => PAWN
+        res
+    }
+
+    pub fn uci_chess960(&self) -> Vec<u8> {
+        if self.is_king_castling || self.is_queen_castling {
+            let rook_file = if self.is_king_castling {7} else {0};
+            let mut res = self.start.get_str();
+            res.extend(Coord(self.start.0, rook_file).get_str());
+            return res;
+        }
+        self.uci()
+    }
+
+    pub fn from_uci(uci: &[u8], board: &Board) -> std::result::Result<Self, NotationError> {
+        let (start, end, promotion) = match uci {
+            [s0, s1, e0, e1] => ([*s0, *s1], [*e0, *e1], None),
+            [s0, s1, e0, e1, p] => ([*s0, *s1], [*e0, *e1], Some(PieceType::from_char(p).ok_or(NotationError::InvalidSyntax)?)),
+            _ => return Err(NotationError::InvalidSyntax)
         };
-        let piece = Piece {piece_type, color: turn};
-        todo!()
+        let start = Coord::from_str(&start).ok_or(NotationError::InvalidSyntax)?;
+        let end = Coord::from_str(&end).ok_or(NotationError::InvalidSyntax)?;
+        let king_takes_rook = board.get(&start).map(|p| p.piece_type) == Some(KING)
+            && board.get(&end).map(|p| (p.piece_type, p.color)) == Some((ROOK, board.turn()));
+        let mut chess_move = board.available_moves().into_iter().find(|m| {
+            let is_match = if king_takes_rook {
+                m.start == start && if end.1 > start.1 {m.is_king_castling} else {m.is_queen_castling}
+            }
+            else {
+                m.start == start && m.end == end && m.promotion.map(|p| p.piece_type) == promotion
+            };
+            is_match && board.is_legal(m)
+        }).ok_or(NotationError::IllegalMove)?;
+        board.annotate(&mut chess_move);
+        Ok(chess_move)
     }
 }
 
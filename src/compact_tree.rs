@@ -0,0 +1,394 @@
+use crate::{*, piece::PieceType};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+
+const MAGIC: &[u8; 4] = b"CTRB";
+pub const COMPACT_FORMAT_VERSION: u32 = 1;
+const NODE_RECORD_SIZE: usize = 36;
+
+// start square in bits 0-5, end square in bits 6-11, promotion in bits 12-14.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PackedMove(u16);
+
+impl PackedMove {
+    pub fn pack(chess_move: &ChessMove) -> Self {
+        let square = |c: Coord| (c.0 * 8 + c.1) as u16;
+        let promotion = match chess_move.promotion.map(|p| p.piece_type) {
+            None => 0,
+            Some(KNIGHT) => 1,
+            Some(BISHOP) => 2,
+            Some(ROOK) => 3,
+            Some(_) => 4
+        };
+        PackedMove(square(chess_move.start) | square(chess_move.end) << 6 | promotion << 12)
+    }
+
+    pub fn from_bits(bits: u16) -> Self {
+        PackedMove(bits)
+    }
+
+    pub fn bits(&self) -> u16 {
+        self.0
+    }
+
+    pub fn start(&self) -> Coord {
+        Coord((self.0 >> 3 & 7) as isize, (self.0 & 7) as isize)
+    }
+
+    pub fn end(&self) -> Coord {
+        Coord((self.0 >> 9 & 7) as isize, (self.0 >> 6 & 7) as isize)
+    }
+
+    pub fn promotion(&self) -> Option<PieceType> {
+        match self.0 >> 12 & 7 {
+            1 => Some(KNIGHT),
+            2 => Some(BISHOP),
+            3 => Some(ROOK),
+            4 => Some(QUEEN),
+            _ => None
+        }
+    }
+
+    pub fn uci(&self) -> Vec<u8> {
+        let mut res = self.start().get_str();
+        res.extend(self.end().get_str());
+        if let Some(p) = self.promotion() {
+            res.push(p.get_char().to_ascii_lowercase());
+        }
+        res
+    }
+
+    pub fn unpack(&self, board: &Board) -> std::result::Result<ChessMove, NotationError> {
+        ChessMove::from_uci(&self.uci(), board)
+    }
+
+    fn unpack_stored(&self, board: &Board) -> std::result::Result<ChessMove, TreeFileError> {
+        self.unpack(board).map_err(|e| TreeFileError::InvalidMove(String::from_utf8_lossy(&self.uci()).into_owned(), e))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct CompactNode {
+    packed_move: PackedMove,
+    last_year: u16,
+    parent: u32,
+    first_child: u32,
+    child_count: u32,
+    games: u32,
+    white_wins: u32,
+    draws: u32,
+    black_wins: u32,
+    elo_count: u32,
+    elo_sum: u64
+}
+
+impl CompactNode {
+    fn new(packed_move: PackedMove, parent: u32, stats: &NodeStats) -> Self {
+        CompactNode {
+            packed_move,
+            last_year: stats.last_year.unwrap_or(0),
+            parent,
+            first_child: 0,
+            child_count: 0,
+            games: stats.games as u32,
+            white_wins: stats.white_wins as u32,
+            draws: stats.draws as u32,
+            black_wins: stats.black_wins as u32,
+            elo_count: stats.elo_count as u32,
+            elo_sum: stats.elo_sum
+        }
+    }
+
+    fn stats(&self) -> NodeStats {
+        NodeStats {
+            games: self.games as usize,
+            white_wins: self.white_wins as usize,
+            draws: self.draws as usize,
+            black_wins: self.black_wins as usize,
+            elo_sum: self.elo_sum,
+            elo_count: self.elo_count as usize,
+            last_year: if self.last_year == 0 {None} else {Some(self.last_year)}
+        }
+    }
+}
+
+// Nodes live in one arena in breadth-first order, so the children of a node are
+// a contiguous range and only the root position is stored.
+#[derive(Debug, Clone)]
+pub struct CompactTree {
+    position: Board,
+    nodes: Vec<CompactNode>
+}
+
+impl CompactTree {
+    pub const ROOT: usize = 0;
+
+    pub fn from_tree(tree: &OpeningTree) -> Self {
+        let mut nodes = vec![CompactNode::new(PackedMove::default(), 0, tree.stats())];
+        let mut queue = std::collections::VecDeque::from([tree]);
+        let mut index = 0;
+        while let Some(node) = queue.pop_front() {
+            let children = node.children_by_popularity();
+            nodes[index].first_child = nodes.len() as u32;
+            nodes[index].child_count = children.len() as u32;
+            for (chess_move, child) in children {
+                nodes.push(CompactNode::new(PackedMove::pack(chess_move), index as u32, child.stats()));
+                queue.push_back(child);
+            }
+            index += 1;
+        }
+        CompactTree {position: *tree.position(), nodes}
+    }
+
+    // Moves are only checked against their position here, so a damaged file
+    // loads but fails with `InvalidMove`.
+    pub fn to_tree(&self) -> std::result::Result<OpeningTree, TreeFileError> {
+        self.subtree(Self::ROOT, &self.position)
+    }
+
+    fn subtree(&self, id: usize, board: &Board) -> std::result::Result<OpeningTree, TreeFileError> {
+        let mut tree = OpeningTree::new_from_position(board);
+        tree.set_stats(self.nodes[id].stats());
+        for child in self.children(id) {
+            let chess_move = self.nodes[child].packed_move.unpack_stored(board)?;
+            let mut next = *board;
+            next.do_move(&chess_move);
+            tree.insert_child(chess_move, self.subtree(child, &next)?);
+        }
+        Ok(tree)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn root_position(&self) -> &Board {
+        &self.position
+    }
+
+    pub fn children(&self, id: usize) -> std::ops::Range<usize> {
+        let node = &self.nodes[id];
+        node.first_child as usize..(node.first_child + node.child_count) as usize
+    }
+
+    pub fn parent(&self, id: usize) -> Option<usize> {
+        if id == Self::ROOT {None} else {Some(self.nodes[id].parent as usize)}
+    }
+
+    pub fn packed_move(&self, id: usize) -> Option<PackedMove> {
+        self.parent(id).map(|_| self.nodes[id].packed_move)
+    }
+
+    pub fn stats(&self, id: usize) -> NodeStats {
+        self.nodes[id].stats()
+    }
+
+    pub fn line(&self, id: usize) -> std::result::Result<Vec<ChessMove>, TreeFileError> {
+        let mut packed = vec![];
+        let mut node = id;
+        while let Some(parent) = self.parent(node) {
+            packed.push(self.nodes[node].packed_move);
+            node = parent;
+        }
+        let mut board = self.position;
+        packed.iter().rev().map(|m| {
+            let chess_move = m.unpack_stored(&board)?;
+            board.do_move(&chess_move);
+            Ok(chess_move)
+        }).collect()
+    }
+
+    pub fn position(&self, id: usize) -> std::result::Result<Board, TreeFileError> {
+        let mut board = self.position;
+        for chess_move in self.line(id)? {
+            board.do_move(&chess_move);
+        }
+        Ok(board)
+    }
+
+    pub fn find(&self, moves: &[ChessMove]) -> Option<usize> {
+        moves.iter().try_fold(Self::ROOT, |id, chess_move| {
+            let packed = PackedMove::pack(chess_move);
+            self.children(id).find(|&child| self.nodes[child].packed_move == packed)
+        })
+    }
+
+    pub fn save(&self, file_name: &str) -> std::result::Result<(), TreeFileError> {
+        let mut writer = BufWriter::new(File::create(file_name)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load(file_name: &str) -> std::result::Result<Self, TreeFileError> {
+        CompactTree::read_from(File::open(file_name)?)
+    }
+
+    // Layout (little endian): magic, version u32, FEN length u16, FEN, node count u32,
+    // then one fixed-size record per node in arena order.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> std::result::Result<(), TreeFileError> {
+        let fen = self.position.to_fen();
+        let mut buf = Vec::with_capacity(14 + fen.len() + self.nodes.len() * NODE_RECORD_SIZE);
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&COMPACT_FORMAT_VERSION.to_le_bytes());
+        buf.extend_from_slice(&(fen.len() as u16).to_le_bytes());
+        buf.extend_from_slice(&fen);
+        buf.extend_from_slice(&(self.nodes.len() as u32).to_le_bytes());
+        for node in &self.nodes {
+            buf.extend_from_slice(&node.packed_move.0.to_le_bytes());
+            buf.extend_from_slice(&node.child_count.to_le_bytes());
+            buf.extend_from_slice(&node.games.to_le_bytes());
+            buf.extend_from_slice(&node.white_wins.to_le_bytes());
+            buf.extend_from_slice(&node.draws.to_le_bytes());
+            buf.extend_from_slice(&node.black_wins.to_le_bytes());
+            buf.extend_from_slice(&node.elo_count.to_le_bytes());
+            buf.extend_from_slice(&node.elo_sum.to_le_bytes());
+            buf.extend_from_slice(&node.last_year.to_le_bytes());
+        }
+        writer.write_all(&buf)?;
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(mut reader: R) -> std::result::Result<Self, TreeFileError> {
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf)?;
+        let mut input = Input {buf: &buf, pos: 0};
+        if input.take(4)? != MAGIC {return Err(TreeFileError::Corrupt("not a compact tree file"))}
+        let version = input.u32()?;
+        if version != COMPACT_FORMAT_VERSION {return Err(TreeFileError::UnsupportedVersion(version as u64))}
+        let fen_len = input.u16()? as usize;
+        let fen = input.take(fen_len)?;
+        let position = Board::try_from_fen(fen)
+            .map_err(|e| TreeFileError::InvalidFen(String::from_utf8_lossy(fen).into_owned(), e))?;
+        let count = input.u32()? as usize;
+        if buf.len() - input.pos != count * NODE_RECORD_SIZE {return Err(TreeFileError::Corrupt("wrong file size"))}
+        let mut nodes = Vec::with_capacity(count);
+        let mut next_child = 1u64;
+        for index in 0..count {
+            let mut node = CompactNode {
+                packed_move: PackedMove(input.u16()?),
+                child_count: input.u32()?,
+                games: input.u32()?,
+                white_wins: input.u32()?,
+                draws: input.u32()?,
+                black_wins: input.u32()?,
+                elo_count: input.u32()?,
+                elo_sum: input.u64()?,
+                last_year: input.u16()?,
+                ..CompactNode::default()
+            };
+            if index > 0 && (node.packed_move.0 >> 12 > 4 || node.packed_move.start() == node.packed_move.end()) {
+                return Err(TreeFileError::Corrupt("invalid packed move"));
+            }
+            node.first_child = next_child as u32;
+            next_child += node.child_count as u64;
+            if next_child > count as u64 {return Err(TreeFileError::Corrupt("child index out of range"))}
+            // Children always come after their parent in breadth-first order.
+            if node.child_count > 0 && node.first_child as usize <= index {return Err(TreeFileError::Corrupt("child index out of range"))}
+            nodes.push(node);
+        }
+        if next_child != count as u64 {return Err(TreeFileError::Corrupt("orphan nodes"))}
+        for index in 0..count {
+            let node = nodes[index];
+            for child in node.first_child..node.first_child + node.child_count {
+                nodes[child as usize].parent = index as u32;
+            }
+        }
+        Ok(CompactTree {position, nodes})
+    }
+}
+
+struct Input<'a> {
+    buf: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Input<'a> {
+    fn take(&mut self, len: usize) -> std::result::Result<&'a [u8], TreeFileError> {
+        let bytes = self.buf.get(self.pos..self.pos + len).ok_or(TreeFileError::Corrupt("unexpected end of file"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> std::result::Result<u16, TreeFileError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> std::result::Result<u32, TreeFileError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> std::result::Result<u64, TreeFileError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_tree() -> OpeningTree {
+        let pgn = "1. e4 e5 (1... c5 2. Nf3 d6 3. d4 cxd4) 2. Nf3 Nc6 (2... d6 3. d4 exd4) 3. Bb5 *";
+        OpeningTree::from_game_variations(&Game::from_pgn(pgn).unwrap())
+    }
+
+    fn bytes(tree: &CompactTree) -> Vec<u8> {
+        let mut buf = vec![];
+        tree.write_to(&mut buf).unwrap();
+        buf
+    }
+
+    // Offset of the move of node `id` in a file written from the start position.
+    fn move_offset(id: usize) -> usize {
+        4 + 4 + 2 + Board::default().to_fen().len() + 4 + id * NODE_RECORD_SIZE
+    }
+
+    #[test]
+    fn packed_move_round_trip() {
+        let board = Board::try_from_fen(b"4k3/1P6/8/8/8/8/8/4K2R w K - 0 1").unwrap();
+        for chess_move in board.legal_moves() {
+            assert_eq!(PackedMove::pack(&chess_move).unpack(&board), Ok(chess_move));
+        }
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let tree = sample_tree();
+        let compact = CompactTree::from_tree(&tree);
+        let loaded = CompactTree::read_from(bytes(&compact).as_slice()).unwrap();
+        assert_eq!(loaded.len(), compact.len());
+        assert_eq!(bytes(&loaded), bytes(&compact));
+        assert_eq!(loaded.to_tree().unwrap().get_leafs().len(), tree.get_leafs().len());
+        let last = loaded.len() - 1;
+        let line = loaded.line(last).unwrap();
+        assert_eq!(loaded.find(&line), Some(last));
+        assert_eq!(tree.node(&line).map(|node| node.position().to_fen()), Some(loaded.position(last).unwrap().to_fen()));
+    }
+
+    #[test]
+    fn corrupt_moves_are_errors() {
+        let mut buf = bytes(&CompactTree::from_tree(&sample_tree()));
+        let offset = move_offset(1);
+        buf[offset..offset + 2].copy_from_slice(&0x0FFFu16.to_le_bytes());
+        assert!(matches!(CompactTree::read_from(buf.as_slice()), Err(TreeFileError::Corrupt(_))));
+
+        // a1-a2 is well formed but illegal from the start position.
+        let a1a2 = PackedMove::from_bits(8 << 6);
+        buf[offset..offset + 2].copy_from_slice(&a1a2.bits().to_le_bytes());
+        let loaded = CompactTree::read_from(buf.as_slice()).unwrap();
+        assert!(matches!(loaded.to_tree(), Err(TreeFileError::InvalidMove(_, _))));
+        assert!(loaded.line(1).is_err());
+        assert!(loaded.position(1).is_err());
+    }
+
+    #[test]
+    fn truncated_file_is_an_error() {
+        let buf = bytes(&CompactTree::from_tree(&sample_tree()));
+        assert!(CompactTree::read_from(&buf[..buf.len() - 1]).is_err());
+        assert!(CompactTree::read_from(&b"CTRB"[..]).is_err());
+    }
+}
@@ -2,38 +2,215 @@ use crate::*;
 use rand::thread_rng;
 use rand::seq::SliceRandom;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MoveNode {
+    pub chess_move: ChessMove,
+    pub nags: Vec<u8>,
+    pub comment: Option<String>,
+    pub variations: Vec<Variation>
+}
+
+impl MoveNode {
+    pub fn new(chess_move: ChessMove) -> Self {
+        MoveNode {chess_move, nags: vec![], comment: None, variations: vec![]}
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Variation {
+    pub comment: Option<String>,
+    pub nodes: Vec<MoveNode>
+}
+
+impl Variation {
+    pub fn moves(&self) -> Vec<ChessMove> {
+        self.nodes.iter().map(|node| node.chess_move).collect()
+    }
+}
+
+pub(crate) fn add_comment(comment: &mut Option<String>, text: &str) {
+    match comment {
+        Some(c) => {
+            c.push(' ');
+            c.push_str(text);
+        }
+        None => *comment = Some(text.to_string())
+    }
+}
+
+pub struct Plies<'a> {
+    stack: Vec<(std::slice::Iter<'a, MoveNode>, Board)>,
+    include_variations: bool
+}
+
+impl<'a> Plies<'a> {
+    pub fn new(variation: &'a Variation, board: &Board, include_variations: bool) -> Self {
+        Plies {stack: vec![(variation.nodes.iter(), *board)], include_variations}
+    }
+}
+
+impl Iterator for Plies<'_> {
+    type Item = (Board, ChessMove, Board);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (nodes, board) = self.stack.last_mut()?;
+            let node = match nodes.next() {
+                Some(node) => node,
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+            };
+            let before = *board;
+            board.do_move(&node.chess_move);
+            let after = *board;
+            if self.include_variations {
+                for variation in node.variations.iter().rev() {
+                    self.stack.push((variation.nodes.iter(), before));
+                }
+            }
+            return Some((before, node.chess_move, after));
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Game {
-    moves: Vec<ChessMove>,
-    result: Option<Result>
+    pub(crate) headers: Headers,
+    pub(crate) initial_board: Board,
+    pub(crate) mainline: Variation,
+    pub(crate) result: Option<Result>
 }
 
 impl Game {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Game {
-            moves: Vec::<ChessMove>::new(),
+            headers: Headers::new(),
+            initial_board: Board::default(),
+            mainline: Variation::default(),
             result: None
         }
     }
 
-    // pub fn from_pgn(pgn: &str) -> Self {
-    //     //println!("{}", pgn);
-    //     let mut game = Game::new();
-    //     game
-    // }
-
-    // pub fn random() -> Self {
-    //     let mut game = Game::new();
-    //     let mut board = Board::default();
-    //     loop {
-    //         let available_moves = board.available_moves();
-    //         let chess_move = available_moves.choose(&mut thread_rng());
-    //         if chess_move.is_none() {break;}
-    //         let chess_move = chess_move.unwrap();
-    //         game.moves.push(*chess_move);
-    //         board.do_move(&chess_move);
-    //     }
-    //     game.result = Some(Result::Draw);
-    //     game
-    // }
-}
\ No newline at end of file
+    pub fn from_position(board: &Board) -> Self {
+        let mut game = Game::new();
+        if board.to_fen() != Board::default().to_fen() {
+            game.headers.set("SetUp", "1");
+            game.headers.set("FEN", &String::from_utf8_lossy(&board.to_fen()));
+        }
+        game.initial_board = *board;
+        game
+    }
+
+    pub fn from_pgn(pgn: &str) -> std::result::Result<Self, PgnError> {
+        let mut parser = Parser::from_reader(pgn.as_bytes());
+        parser.next().unwrap_or_else(|| Ok(Game::new()))
+    }
+
+    pub fn to_pgn(&self) -> String {
+        format!("{}\n{}", writer::write_headers(self), writer::write_movetext(self))
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    pub fn initial_board(&self) -> Board {
+        self.initial_board
+    }
+
+    pub fn moves(&self) -> Vec<ChessMove> {
+        self.mainline.moves()
+    }
+
+    pub fn mainline(&self) -> &Variation {
+        &self.mainline
+    }
+
+    pub fn result(&self) -> Option<Result> {
+        self.result
+    }
+
+    pub fn mainline_plies(&self) -> Plies<'_> {
+        Plies::new(&self.mainline, &self.initial_board, false)
+    }
+
+    pub fn plies(&self) -> Plies<'_> {
+        Plies::new(&self.mainline, &self.initial_board, true)
+    }
+
+    pub fn fens(&self) -> impl Iterator<Item = FEN> + '_ {
+        std::iter::once(self.initial_board.to_fen()).chain(self.mainline_plies().map(|(_, _, after)| after.to_fen()))
+    }
+
+    pub fn status(&self) -> Status {
+        let mut board = self.initial_board;
+        let mut history = Vec::<Board>::new();
+        for (before, _, after) in self.mainline_plies() {
+            history.push(before);
+            board = after;
+        }
+        board.status_with_history(&history)
+    }
+
+    pub fn random() -> Self {
+        let mut game = Game::new();
+        let mut board = Board::default();
+        let mut history = Vec::<Board>::new();
+        let mut status = board.status();
+        while !status.is_over() {
+            let available_moves = board.legal_moves();
+            let chess_move = available_moves.choose(&mut thread_rng()).unwrap();
+            game.mainline.nodes.push(MoveNode::new(*chess_move));
+            history.push(board);
+            board.do_move(chess_move);
+            status = board.status_with_history(&history);
+        }
+        game.result = status.result();
+        game
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notation(plies: Plies<'_>) -> Vec<String> {
+        plies.map(|(before, chess_move, _)| String::from_utf8_lossy(&chess_move.notation(&before)).into_owned()).collect()
+    }
+
+    #[test]
+    fn plies_visit_variations_depth_first() {
+        let game = Game::from_pgn("1. e4 e5 (1... c5 2. Nf3 (2. c3) 2... d6) (1... e6) 2. Nf3 *").unwrap();
+        assert_eq!(notation(game.plies()), vec!["e4", "e5", "c5", "Nf3", "c3", "d6", "e6", "Nf3"]);
+        assert_eq!(notation(game.mainline_plies()), vec!["e4", "e5", "Nf3"]);
+        let plies: Vec<(Board, ChessMove, Board)> = game.plies().collect();
+        assert_eq!(plies[2].0.to_fen(), plies[1].0.to_fen());
+        assert_eq!(plies[4].0.to_fen(), plies[3].0.to_fen());
+        assert_eq!(plies[5].0.to_fen(), plies[3].2.to_fen());
+        assert_eq!(plies[7].0.to_fen(), plies[1].2.to_fen());
+    }
+
+    #[test]
+    fn fens_follow_the_mainline() {
+        let game = Game::from_pgn("1. e4 (1. d4) 1... c5 *").unwrap();
+        let fens: Vec<String> = game.fens().map(|fen| String::from_utf8_lossy(&fen).into_owned()).collect();
+        assert_eq!(fens, vec![
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+            "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq c6 0 2"
+        ]);
+    }
+
+    #[test]
+    fn status_uses_the_mainline_history() {
+        let game = Game::from_pgn("1. Nf3 Nf6 2. Ng1 Ng8 3. Nf3 Nf6 4. Ng1 (4. e4) 4... Ng8 *").unwrap();
+        assert_eq!(game.status(), Status::ThreefoldRepetition);
+        let game = Game::from_pgn("1. Nf3 Nf6 2. Ng1 Ng8 3. Nf3 Nf6 4. Ng1 *").unwrap();
+        assert_eq!(game.status(), Status::Ongoing);
+    }
+}
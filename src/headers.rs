@@ -0,0 +1,221 @@
+use crate::*;
+use std::fmt;
+
+pub const SEVEN_TAG_ROSTER: [&str; 7] = ["Event", "Site", "Date", "Round", "White", "Black", "Result"];
+
+const KNOWN_TAGS: [&str; 20] = [
+    "Event", "Site", "Date", "Round", "White", "Black", "Result",
+    "WhiteElo", "BlackElo", "ECO", "Opening", "Variation", "EventDate", "PlyCount",
+    "Annotator", "TimeControl", "Termination", "SetUp", "FEN", "Variant"
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PgnDate {
+    pub year: Option<u16>,
+    pub month: Option<u8>,
+    pub day: Option<u8>
+}
+
+impl PgnDate {
+    pub fn parse(date: &str) -> Option<Self> {
+        fn part<T: std::str::FromStr>(part: &str, len: usize) -> Option<Option<T>> {
+            if part.len() != len {return None}
+            if part.chars().all(|c| c == '?') {return Some(None)}
+            part.parse().ok().map(Some)
+        }
+        let mut parts = date.trim().split('.');
+        let year = part(parts.next()?, 4)?;
+        let month = match parts.next() {Some(p) => part(p, 2)?, None => None};
+        let day = match parts.next() {Some(p) => part(p, 2)?, None => None};
+        if parts.next().is_some() {return None}
+        Some(PgnDate {year, month, day})
+    }
+}
+
+impl fmt::Display for PgnDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.year {
+            Some(year) => write!(f, "{:04}.", year)?,
+            None => write!(f, "????.")?
+        }
+        match self.month {
+            Some(month) => write!(f, "{:02}.", month)?,
+            None => write!(f, "??.")?
+        }
+        match self.day {
+            Some(day) => write!(f, "{:02}", day),
+            None => write!(f, "??")
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    tags: Vec<(String, String)>
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Headers {tags: vec![]}
+    }
+
+    pub fn normalize_name(name: &str) -> &str {
+        KNOWN_TAGS.iter().find(|known| known.eq_ignore_ascii_case(name)).copied().unwrap_or(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        let name = Headers::normalize_name(name);
+        self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) {
+        let name = Headers::normalize_name(name);
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string()))
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let name = Headers::normalize_name(name);
+        let index = self.tags.iter().position(|(tag, _)| tag == name)?;
+        Some(self.tags.remove(index).1)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.tags.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.tags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+
+    fn known(&self, name: &str) -> Option<&str> {
+        self.get(name).filter(|value| !value.is_empty() && *value != "?" && *value != "-")
+    }
+
+    pub fn event(&self) -> Option<&str> {
+        self.known("Event")
+    }
+
+    pub fn site(&self) -> Option<&str> {
+        self.known("Site")
+    }
+
+    pub fn date(&self) -> Option<PgnDate> {
+        self.get("Date").and_then(PgnDate::parse)
+    }
+
+    pub fn round(&self) -> Option<&str> {
+        self.known("Round")
+    }
+
+    pub fn white(&self) -> Option<&str> {
+        self.known("White")
+    }
+
+    pub fn black(&self) -> Option<&str> {
+        self.known("Black")
+    }
+
+    pub fn result(&self) -> Option<Result> {
+        match self.get("Result")? {
+            "1-0" => Some(Result::White),
+            "0-1" => Some(Result::Black),
+            "1/2-1/2" => Some(Result::Draw),
+            _ => None
+        }
+    }
+
+    pub fn white_elo(&self) -> Option<u32> {
+        self.known("WhiteElo")?.parse().ok()
+    }
+
+    pub fn black_elo(&self) -> Option<u32> {
+        self.known("BlackElo")?.parse().ok()
+    }
+
+    pub fn eco(&self) -> Option<&str> {
+        self.known("ECO")
+    }
+
+    pub fn opening(&self) -> Option<&str> {
+        self.known("Opening")
+    }
+
+    pub fn variation(&self) -> Option<&str> {
+        self.known("Variation")
+    }
+
+    pub fn event_date(&self) -> Option<PgnDate> {
+        self.get("EventDate").and_then(PgnDate::parse)
+    }
+
+    pub fn ply_count(&self) -> Option<usize> {
+        self.known("PlyCount")?.parse().ok()
+    }
+
+    pub fn annotator(&self) -> Option<&str> {
+        self.known("Annotator")
+    }
+
+    pub fn time_control(&self) -> Option<&str> {
+        self.known("TimeControl")
+    }
+
+    pub fn termination(&self) -> Option<&str> {
+        self.known("Termination")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_normalised_and_order_kept() {
+        let mut headers = Headers::new();
+        headers.set("White", "Carlsen");
+        headers.set("WhiteELO", "2850");
+        headers.set("eco", "C65");
+        headers.set("MyTag", "x");
+        headers.set("WHITE", "Magnus Carlsen");
+        let tags: Vec<(&str, &str)> = headers.iter().collect();
+        assert_eq!(tags, vec![("White", "Magnus Carlsen"), ("WhiteElo", "2850"), ("ECO", "C65"), ("MyTag", "x")]);
+        assert_eq!(headers.white_elo(), Some(2850));
+        assert_eq!(headers.get("whiteelo"), Some("2850"));
+        assert_eq!(headers.eco(), Some("C65"));
+        assert_eq!(headers.get("mytag"), None);
+        assert_eq!(headers.remove("ECO"), Some(String::from("C65")));
+        assert_eq!(headers.len(), 3);
+    }
+
+    #[test]
+    fn typed_accessors() {
+        let pgn = "[Event \"?\"]\n[Site \"\"]\n[Date \"2023.??.??\"]\n[Result \"1/2-1/2\"]\n[BlackElo \"-\"]\n\
+                   [PlyCount \"2\"]\n[EventDate \"2023.05.??\"]\n\n1. e4 e5 1/2-1/2\n";
+        let game = Parser::from_reader(pgn.as_bytes()).next().unwrap().unwrap();
+        let headers = game.headers();
+        assert_eq!((headers.event(), headers.site(), headers.black_elo()), (None, None, None));
+        assert_eq!(headers.date(), Some(PgnDate {year: Some(2023), month: None, day: None}));
+        assert_eq!(headers.event_date(), Some(PgnDate {year: Some(2023), month: Some(5), day: None}));
+        assert_eq!(headers.result(), Some(Result::Draw));
+        assert_eq!(headers.ply_count(), Some(2));
+    }
+
+    #[test]
+    fn partial_dates() {
+        assert_eq!(PgnDate::parse("????.??.??"), Some(PgnDate {year: None, month: None, day: None}));
+        assert_eq!(PgnDate::parse("1999.12.31"), Some(PgnDate {year: Some(1999), month: Some(12), day: Some(31)}));
+        assert_eq!(PgnDate::parse("1999"), Some(PgnDate {year: Some(1999), month: None, day: None}));
+        assert_eq!(PgnDate::parse("99.12.31"), None);
+        assert_eq!(PgnDate::parse("1999.1.31"), None);
+        assert_eq!(PgnDate::parse("1999.12.31.1"), None);
+        assert_eq!(PgnDate::parse("1999.??.31").unwrap().to_string(), "1999.??.31");
+        assert_eq!(PgnDate::parse("1999").unwrap().to_string(), "1999.??.??");
+    }
+}
@@ -22,10 +22,12 @@ impl Coord {
         self.0 >= 0 && self.0 < 8 && self.1 >= 0 && self.1 < 8
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &[u8]) -> Option<Coord> {
         if s.len()!=2 {return None;}
-        Some(Coord((s[0]- b'a') as isize,
-            (s[1]-b'0' -1) as isize))
+        let coord = Coord(s[1] as isize - b'1' as isize,
+            s[0] as isize - b'a' as isize);
+        if coord.is_correct() {Some(coord)} else {None}
     }
 
     pub fn get_char_column(&self) -> u8 {
@@ -37,10 +39,7 @@ impl Coord {
     }
 
     pub fn get_str(&self) -> Vec<u8> {
-        let mut res = vec![];
-        res.push(self.get_char_column());
-        res.push(self.get_char_line());
-        res
+        vec![self.get_char_column(), self.get_char_line()]
     }
 }
 
@@ -70,7 +69,7 @@ pub const BOARD_SIZE : usize = 8;
 pub const WHITE: Color = Color::WHITE;
 pub const BLACK: Color = Color::BLACK;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Result {
     White, 
     Black,
@@ -79,20 +78,43 @@ pub enum Result {
 
 pub type FEN = Vec<u8>;
 
+mod zobrist;
+
 mod board;
-pub use board::Board;
+pub use board::{Board, FenError, Status, Undo};
 
 mod piece;
 pub use piece::{Piece, PieceType::*, PieceWrapper};
 
+mod headers;
+pub use headers::{Headers, PgnDate, SEVEN_TAG_ROSTER};
+
 mod game;
-pub use game::Game;
+pub use game::{Game, MoveNode, Plies, Variation};
 
 mod chess_move;
-pub use chess_move::ChessMove;
+pub use chess_move::{ChessMove, NotationError};
 
 mod opening_tree;
-pub use opening_tree::OpeningTree;
+pub use opening_tree::{NodeStats, OpeningTree, TreeFileError, TREE_FORMAT_VERSION};
+
+mod opening_graph;
+pub use opening_graph::{GraphNode, OpeningGraph};
+
+mod compact_tree;
+pub use compact_tree::{CompactTree, PackedMove, COMPACT_FORMAT_VERSION};
+
+mod polyglot;
+pub use polyglot::{PolyglotBook, PolyglotEntry, PolyglotKeys};
+
+mod repertoire;
+pub use repertoire::{CoverageGap, Repertoire, RepertoireIssue, RepertoireIssueKind};
+
+mod tree_diff;
+pub use tree_diff::{ConflictKind, MergeConflict, MergeResult, TreeChange, TreeChangeKind};
+
+mod writer;
+pub use writer::PgnWriter;
 
 mod parser;
-pub use parser::Parser;
\ No newline at end of file
+pub use parser::{Parser, PgnError, PgnErrorKind, ImportSummary};
\ No newline at end of file
@@ -1,35 +1,48 @@
 use chess_trainer::*;
 
 fn main() {
-    // let game = Game::from_pgn("[Event \"?\"]
-    // [Site \"?\"]
-    // [Date \"????.??.??\"]
-    // [Round \"?\"]
-    // [White \"?\"]
-    // [Black \"?\"]
-    // [Result \"*\"]
-    // [WhiteElo \"2666\"]
-    // [BlackElo \"2804\"]
-    // [ECO \"B43b\"]
+    let game = Game::from_pgn("[Event \"?\"]
+    [Site \"?\"]
+    [Date \"????.??.??\"]
+    [Round \"?\"]
+    [White \"?\"]
+    [Black \"?\"]
+    [Result \"*\"]
+    [WhiteElo \"2666\"]
+    [BlackElo \"2804\"]
+    [ECO \"B43b\"]
     
-    // 1.e4 c5 2.Nf3 e6 3.d4 cxd4 4.Nxd4 a6 5.Nc3 d6 6.g4 Ne7 7.a3 Nbc6 8.Nb3 b5 
-    // 9.h4 Bb7 10.Bf4 Ne5 11.Bxe5 dxe5 12.Qxd8+ Rxd8 13.Nc5 Rb8 14.Nxb7 Nc6 15.
-    // a4 b4 16.Bxa6 bxc3 17.Bb5 Kd7 18.Na5 cxb2 19.Rd1+ Kc7 20.Nxc6 *");
+    1.e4 c5 2.Nf3 e6 3.d4 cxd4 4.Nxd4 a6 5.Nc3 d6 6.g4 Ne7 7.a3 Nbc6 8.Nb3 b5 
+    9.h4 Bb7 10.Bf4 Ne5 11.Bxe5 dxe5 12.Qxd8+ Rxd8 13.Nc5 Rb8 14.Nxb7 Nc6 15.
+    a4 b4 16.Bxa6 bxc3 17.Bb5 Kd7 18.Na5 cxb2 19.Rd1+ Kc7 20.Nxc6 *");
+    println!("{:?}", game.map(|g| g.moves().len()));
 
     // let opening_file = "myOpening.txt";
-    // let mut my_opening = OpeningTree::load(opening_file);
-    // my_opening.add_d4();
-    // my_opening.save(opening_file);
+    // let mut my_opening = OpeningTree::load(opening_file).unwrap_or_default();
+    // my_opening.add_line(&[ChessMove::from_notation(b"d4", &Board::default()).unwrap()]);
+    // my_opening.save(opening_file).unwrap();
 
-    // let board = Board::default();
-    // let av_moves = board.available_moves();
-    // println!("{:?}", av_moves);
-    // println!("{:?}", av_moves.len());
+    let board = Board::default();
+    let av_moves = board.legal_moves();
+    println!("{:?}", av_moves.len());
 
-    // let game = Game::random(); \\Only basic rules
+    // let game = Game::random();
     // println!("{:?}", game);
 
-    let mut white_opening = OpeningTree::new();
-    //white_opening.add_move(chess_move)
-    let mut parser = Parser::new("small_chess_database.pgn");
+    let mut parser = Parser::new("small_chess_database.pgn").unwrap();
+    let games = parser.by_ref().filter_map(|game| match game {
+        Ok(game) => Some(game),
+        Err(e) => {
+            println!("{}", e);
+            None
+        }
+    });
+    let white_opening = OpeningTree::from_games(games, 12);
+    println!("{}", parser.summary());
+    for (chess_move, node) in white_opening.children_by_popularity() {
+        let stats = node.stats();
+        println!("{} {} games +{} ={} -{} elo {:?} last {:?}",
+            String::from_utf8_lossy(&chess_move.notation(white_opening.position())),
+            stats.games, stats.white_wins, stats.draws, stats.black_wins, stats.average_elo(), stats.last_year);
+    }
 }
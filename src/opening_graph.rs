@@ -0,0 +1,231 @@
+use crate::*;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone)]
+pub struct GraphNode {
+    position: Board,
+    stats: NodeStats,
+    children: HashMap<ChessMove, u64>,
+    parents: Vec<(u64, ChessMove)>
+}
+
+impl GraphNode {
+    fn new(board: &Board) -> Self {
+        GraphNode { position: *board, stats: NodeStats::default(), children: HashMap::new(), parents: vec![] }
+    }
+
+    pub fn position(&self) -> &Board {
+        &self.position
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.position.hash()
+    }
+
+    pub fn stats(&self) -> &NodeStats {
+        &self.stats
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = (&ChessMove, u64)> {
+        self.children.iter().map(|(chess_move, &hash)| (chess_move, hash))
+    }
+
+    pub fn parents(&self) -> &[(u64, ChessMove)] {
+        &self.parents
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    pub fn is_transposition(&self) -> bool {
+        self.parents.len() > 1
+    }
+}
+
+// Positions are keyed by their zobrist hash, so move orders reaching the same
+// placement, side to move, castling rights and en passant square share one node.
+#[derive(Debug, Clone)]
+pub struct OpeningGraph {
+    root: u64,
+    nodes: HashMap<u64, GraphNode>
+}
+
+impl OpeningGraph {
+    pub fn new() -> Self {
+        OpeningGraph::new_from_position(&Board::default())
+    }
+
+    pub fn new_from_position(board: &Board) -> Self {
+        let mut nodes = HashMap::new();
+        nodes.insert(board.hash(), GraphNode::new(board));
+        OpeningGraph { root: board.hash(), nodes }
+    }
+
+    pub fn from_games(games: impl IntoIterator<Item = Game>, max_ply: usize) -> Self {
+        let mut graph = OpeningGraph::new();
+        for game in games {
+            graph.add_game(&game, max_ply);
+        }
+        graph
+    }
+
+    pub fn from_tree(tree: &OpeningTree) -> Self {
+        let mut graph = OpeningGraph::new_from_position(tree.position());
+        graph.merge_tree(graph.root, tree);
+        graph
+    }
+
+    fn merge_tree(&mut self, hash: u64, tree: &OpeningTree) {
+        self.nodes.get_mut(&hash).unwrap().stats.merge(tree.stats());
+        for (chess_move, child) in tree.children() {
+            let child_hash = self.add_move(hash, chess_move).unwrap();
+            self.merge_tree(child_hash, child);
+        }
+    }
+
+    pub fn root(&self) -> &GraphNode {
+        &self.nodes[&self.root]
+    }
+
+    pub fn get(&self, hash: u64) -> Option<&GraphNode> {
+        self.nodes.get(&hash)
+    }
+
+    pub fn get_position(&self, board: &Board) -> Option<&GraphNode> {
+        self.get(board.hash())
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &GraphNode> {
+        self.nodes.values()
+    }
+
+    pub fn transpositions(&self) -> impl Iterator<Item = &GraphNode> {
+        self.nodes.values().filter(|node| node.is_transposition())
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    // Returns the hash of the position after the move, or `None` if `hash` is
+    // not a position of the graph.
+    pub fn add_move(&mut self, hash: u64, chess_move: &ChessMove) -> Option<u64> {
+        let node = self.nodes.get_mut(&hash)?;
+        if let Some(&child) = node.children.get(chess_move) {
+            return Some(child);
+        }
+        let mut board = node.position;
+        board.do_move(chess_move);
+        let child = board.hash();
+        node.children.insert(*chess_move, child);
+        let child_node = self.nodes.entry(child).or_insert_with(|| GraphNode::new(&board));
+        child_node.parents.push((hash, *chess_move));
+        Some(child)
+    }
+
+    pub fn add_line(&mut self, moves: &[ChessMove]) -> u64 {
+        moves.iter().fold(self.root, |hash, chess_move| self.add_move(hash, chess_move).unwrap())
+    }
+
+    pub fn add_game(&mut self, game: &Game, max_ply: usize) -> bool {
+        if game.initial_board().hash() != self.root {return false}
+        let headers = game.headers();
+        let year = headers.date().or(headers.event_date()).and_then(|date| date.year);
+        let result = game.result();
+        let mut visited = HashSet::new();
+        visited.insert(self.root);
+        self.nodes.get_mut(&self.root).unwrap().stats.record(result, None, year);
+        let mut hash = self.root;
+        for (before, chess_move, _) in game.mainline_plies().take(max_ply) {
+            let elo = match before.turn() {
+                Color::WHITE => headers.white_elo(),
+                Color::BLACK => headers.black_elo()
+            }.filter(|&elo| elo > 0);
+            hash = self.add_move(hash, &chess_move).unwrap();
+            // A game going back to an earlier position is only counted once there.
+            if visited.insert(hash) {
+                self.nodes.get_mut(&hash).unwrap().stats.record(result, elo, year);
+            }
+        }
+        true
+    }
+
+    pub fn move_orders(&self, hash: u64) -> Vec<Vec<ChessMove>> {
+        let mut orders = vec![];
+        if self.nodes.contains_key(&hash) {
+            self.collect_move_orders(hash, &mut vec![], &mut HashSet::new(), &mut orders);
+        }
+        orders
+    }
+
+    fn collect_move_orders(&self, hash: u64, suffix: &mut Vec<ChessMove>, on_path: &mut HashSet<u64>, orders: &mut Vec<Vec<ChessMove>>) {
+        if hash == self.root {
+            orders.push(suffix.iter().rev().copied().collect());
+            return;
+        }
+        on_path.insert(hash);
+        for (parent, chess_move) in &self.nodes[&hash].parents {
+            if on_path.contains(parent) {continue}
+            suffix.push(*chess_move);
+            self.collect_move_orders(*parent, suffix, on_path, orders);
+            suffix.pop();
+        }
+        on_path.remove(&hash);
+    }
+}
+
+impl Default for OpeningGraph {
+    fn default() -> Self {
+        OpeningGraph::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn games(pgn: &str) -> Vec<Game> {
+        Parser::from_reader(pgn.as_bytes()).map(|game| game.unwrap()).collect()
+    }
+
+    #[test]
+    fn move_orders_transpose() {
+        let pgn = "[Result \"1-0\"]\n\n1. d4 Nf6 2. c4 e6 1-0\n\n[Result \"0-1\"]\n\n1. c4 e6 2. d4 Nf6 0-1\n\n\
+                   [Result \"1/2-1/2\"]\n\n1. d4 Nf6 2. c4 e6 3. Nc3 1/2-1/2\n";
+        let graph = OpeningGraph::from_games(games(pgn), 10);
+        // The root, three plies of each move order, the shared position and 3. Nc3.
+        assert_eq!(graph.len(), 9);
+        let main = games("1. d4 Nf6 2. c4 e6 *").remove(0);
+        let mut board = main.initial_board();
+        for (_, chess_move, _) in main.mainline_plies() {
+            board.do_move(&chess_move);
+        }
+        let node = graph.get_position(&board).unwrap();
+        assert!(node.is_transposition());
+        assert_eq!(graph.transpositions().count(), 1);
+        let stats = node.stats();
+        assert_eq!((stats.games, stats.white_wins, stats.black_wins, stats.draws), (3, 1, 1, 1));
+        assert_eq!(graph.root().stats().games, 3);
+        let mut orders: Vec<String> = graph.move_orders(node.hash()).iter()
+            .map(|line| writer::write_line(line, graph.root().position()))
+            .collect();
+        orders.sort();
+        assert_eq!(orders, vec!["1. c4 e6 2. d4 Nf6", "1. d4 Nf6 2. c4 e6"]);
+    }
+
+    #[test]
+    fn unknown_positions_are_rejected() {
+        let mut graph = OpeningGraph::new();
+        let e4 = ChessMove::from_notation(b"e4", &Board::default()).unwrap();
+        assert_eq!(graph.add_move(graph.root().hash() ^ 1, &e4), None);
+        assert!(graph.move_orders(graph.root().hash() ^ 1).is_empty());
+        let hash = graph.add_move(graph.root().hash(), &e4).unwrap();
+        assert_eq!(graph.add_move(graph.root().hash(), &e4), Some(hash));
+        assert_eq!(graph.len(), 2);
+    }
+}
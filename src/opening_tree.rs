@@ -1,37 +1,377 @@
 use crate::*;
-// use std::fs::File;
-// use std::io::prelude::*;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
 use serde::{Serialize, Deserialize};
+use serde_json::Value;
 use std::collections::HashMap;
+use std::fmt;
 
+// Bump when the layout of `TreeFile` changes and teach `migrate` how to
+// upgrade the previous version.
+// 1: nested nodes with moves and statistics, and optional repertoire choices,
+//    comments and NAGs.
+// 2: the same nodes as a flat list.
+pub const TREE_FORMAT_VERSION: u32 = 2;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Debug)]
+pub enum TreeFileError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    MissingVersion,
+    UnsupportedVersion(u64),
+    Corrupt(&'static str),
+    InvalidFen(String, FenError),
+    InvalidMove(String, NotationError)
+}
+
+impl fmt::Display for TreeFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TreeFileError::Io(e) => write!(f, "{}", e),
+            TreeFileError::Json(e) => write!(f, "{}", e),
+            TreeFileError::MissingVersion => write!(f, "missing format version"),
+            TreeFileError::UnsupportedVersion(v) => write!(f, "unsupported format version {}", v),
+            TreeFileError::Corrupt(reason) => write!(f, "corrupt file: {}", reason),
+            TreeFileError::InvalidFen(fen, e) => write!(f, "invalid FEN ({}): {}", e, fen),
+            TreeFileError::InvalidMove(uci, e) => write!(f, "{}: {}", e, uci)
+        }
+    }
+}
+
+impl From<std::io::Error> for TreeFileError {
+    fn from(e: std::io::Error) -> Self {
+        TreeFileError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for TreeFileError {
+    fn from(e: serde_json::Error) -> Self {
+        TreeFileError::Json(e)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TreeFile {
+    version: u32,
+    position: String,
+    nodes: Vec<NodeRecord>
+}
+
+// Nodes are stored breadth-first like `CompactTree`, each pointing back to
+// its parent, so that deep lines do not nest the JSON.
+#[derive(Serialize, Deserialize)]
+struct NodeRecord {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parent: Option<usize>,
+    #[serde(rename = "move", default, skip_serializing_if = "Option::is_none")]
+    uci: Option<String>,
+    #[serde(default)]
+    stats: NodeStats,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    choices: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    nags: Vec<u8>
+}
+
+// Version 1 nested every node in its parent's `children` and kept the root's
+// fields next to `version` and `position`.
+fn v1_to_v2(file: Value) -> std::result::Result<Value, TreeFileError> {
+    let Value::Object(mut root) = file else {return Err(TreeFileError::Corrupt("file is not an object"))};
+    let position = root.remove("position").unwrap_or(Value::Null);
+    root.remove("version");
+    let mut nodes = vec![];
+    let mut queue = std::collections::VecDeque::from([(None, Value::Object(root))]);
+    while let Some((parent, node)) = queue.pop_front() {
+        let Value::Object(mut fields) = node else {return Err(TreeFileError::Corrupt("node is not an object"))};
+        let index = nodes.len();
+        if let Some(Value::Array(children)) = fields.remove("children") {
+            queue.extend(children.into_iter().map(|child| (Some(index), child)));
+        }
+        if let Some(parent) = parent {
+            fields.insert(String::from("parent"), Value::from(parent));
+        }
+        nodes.push(Value::Object(fields));
+    }
+    Ok(serde_json::json!({"version": 2, "position": position, "nodes": nodes}))
+}
+
+fn migrate(file: Value, version: u32) -> std::result::Result<Value, TreeFileError> {
+    match version {
+        // Older versions are upgraded one step at a time.
+        1 => migrate(v1_to_v2(file)?, 2),
+        TREE_FORMAT_VERSION => Ok(file),
+        _ => Err(TreeFileError::UnsupportedVersion(version as u64))
+    }
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct NodeStats {
+    pub games: usize,
+    pub white_wins: usize,
+    pub draws: usize,
+    pub black_wins: usize,
+    pub elo_sum: u64,
+    pub elo_count: usize,
+    pub last_year: Option<u16>
+}
+
+impl NodeStats {
+    pub(crate) fn record(&mut self, result: Option<Result>, elo: Option<u32>, year: Option<u16>) {
+        self.games += 1;
+        match result {
+            Some(Result::White) => self.white_wins += 1,
+            Some(Result::Draw) => self.draws += 1,
+            Some(Result::Black) => self.black_wins += 1,
+            None => ()
+        }
+        if let Some(elo) = elo {
+            self.elo_sum += elo as u64;
+            self.elo_count += 1;
+        }
+        self.last_year = self.last_year.max(year);
+    }
+
+    pub fn merge(&mut self, other: &NodeStats) {
+        self.games += other.games;
+        self.white_wins += other.white_wins;
+        self.draws += other.draws;
+        self.black_wins += other.black_wins;
+        self.elo_sum += other.elo_sum;
+        self.elo_count += other.elo_count;
+        self.last_year = self.last_year.max(other.last_year);
+    }
+
+    pub fn average_elo(&self) -> Option<u32> {
+        if self.elo_count == 0 {return None}
+        Some((self.elo_sum / self.elo_count as u64) as u32)
+    }
+
+    pub fn white_score(&self) -> Option<f64> {
+        let decided = self.white_wins + self.draws + self.black_wins;
+        if decided == 0 {return None}
+        Some((self.white_wins as f64 + self.draws as f64 / 2.) / decided as f64)
+    }
+}
+
+impl fmt::Display for NodeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} games, +{} ={} -{}", self.games, self.white_wins, self.draws, self.black_wins)?;
+        if let Some(elo) = self.average_elo() {
+            write!(f, ", Elo {}", elo)?;
+        }
+        if let Some(year) = self.last_year {
+            write!(f, ", last played {}", year)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct OpeningTree {
     root: HashMap<ChessMove, OpeningTree>,
-    position: Board
+    // Moves of `root` in the order they were added, so that exports keep the
+    // source's mainline first among equally popular moves.
+    move_order: Vec<ChessMove>,
+    position: Board,
+    stats: NodeStats,
+    choices: Vec<ChessMove>,
+    comment: Option<String>,
+    nags: Vec<u8>
 }
 
 impl OpeningTree {
     pub fn new() -> Self {
-        OpeningTree {root: HashMap::new(), position: Board::default()}
+        OpeningTree::new_from_position(&Board::default())
     }
 
     pub fn new_from_position(board: &Board) -> Self {
-        OpeningTree { root: HashMap::new(), position: *board }
+        OpeningTree { root: HashMap::new(), move_order: vec![], position: *board, stats: NodeStats::default(), choices: vec![], comment: None, nags: vec![] }
     }
 
-    // pub fn save(&self, file_name: &str) {
-    //     let mut file = File::create(file_name).unwrap();
-    //     let serialized = serde_json::to_string(&self).unwrap();
-    //     file.write_all(serialized.as_bytes());
-    // }
+    pub fn from_games(games: impl IntoIterator<Item = Game>, max_ply: usize) -> Self {
+        let mut tree = OpeningTree::new();
+        for game in games {
+            tree.add_game(&game, max_ply);
+        }
+        tree
+    }
 
-    // pub fn load(file_name: &str) -> OpeningTree {
-    //     let mut file = File::open(file_name).unwrap();
-    //     let mut contents = String::new();
-    //     file.read_to_string(&mut contents);
-    //     serde_json::from_str(&contents).unwrap()
-    // }
+    pub fn position(&self) -> &Board {
+        &self.position
+    }
+
+    pub fn stats(&self) -> &NodeStats {
+        &self.stats
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = (&ChessMove, &OpeningTree)> {
+        self.move_order.iter().map(|chess_move| (chess_move, &self.root[chess_move]))
+    }
+
+    pub(crate) fn set_stats(&mut self, stats: NodeStats) {
+        self.stats = stats;
+    }
+
+    pub(crate) fn insert_child(&mut self, chess_move: ChessMove, child: OpeningTree) {
+        if self.root.insert(chess_move, child).is_none() {
+            self.move_order.push(chess_move);
+        }
+    }
+
+    pub fn child(&self, chess_move: &ChessMove) -> Option<&OpeningTree> {
+        self.root.get(chess_move)
+    }
+
+    pub fn node(&self, line: &[ChessMove]) -> Option<&OpeningTree> {
+        line.iter().try_fold(self, |node, chess_move| node.root.get(chess_move))
+    }
+
+    pub(crate) fn node_mut(&mut self, line: &[ChessMove]) -> Option<&mut OpeningTree> {
+        line.iter().try_fold(self, |node, chess_move| node.root.get_mut(chess_move))
+    }
+
+    pub fn choices(&self) -> &[ChessMove] {
+        &self.choices
+    }
+
+    pub fn is_chosen(&self, chess_move: &ChessMove) -> bool {
+        self.choices.contains(chess_move)
+    }
+
+    pub(crate) fn choices_mut(&mut self) -> &mut Vec<ChessMove> {
+        &mut self.choices
+    }
+
+    pub(crate) fn set_comment(&mut self, comment: Option<String>) {
+        self.comment = comment;
+    }
+
+    pub(crate) fn set_nags(&mut self, nags: Vec<u8>) {
+        self.nags = nags;
+    }
+
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    pub fn nags(&self) -> &[u8] {
+        &self.nags
+    }
+
+    pub fn children_by_popularity(&self) -> Vec<(&ChessMove, &OpeningTree)> {
+        let mut children: Vec<(&ChessMove, &OpeningTree)> = self.children().collect();
+        children.sort_by_key(|(_, child)| std::cmp::Reverse(child.stats.games));
+        children
+    }
+
+    pub fn save(&self, file_name: &str) -> std::result::Result<(), TreeFileError> {
+        let mut writer = BufWriter::new(File::create(file_name)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load(file_name: &str) -> std::result::Result<Self, TreeFileError> {
+        OpeningTree::read_from(BufReader::new(File::open(file_name)?))
+    }
+
+    pub fn write_to<W: Write>(&self, writer: W) -> std::result::Result<(), TreeFileError> {
+        let file = TreeFile {
+            version: TREE_FORMAT_VERSION,
+            position: String::from_utf8_lossy(&self.position.to_fen()).into_owned(),
+            nodes: self.to_records()
+        };
+        serde_json::to_writer(writer, &file)?;
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: R) -> std::result::Result<Self, TreeFileError> {
+        let value: Value = serde_json::from_reader(reader)?;
+        let version = value.get("version").and_then(Value::as_u64).ok_or(TreeFileError::MissingVersion)?;
+        let version = u32::try_from(version).map_err(|_| TreeFileError::UnsupportedVersion(version))?;
+        let file: TreeFile = serde_json::from_value(migrate(value, version)?)?;
+        let board = Board::try_from_fen(file.position.as_bytes())
+            .map_err(|e| TreeFileError::InvalidFen(file.position.clone(), e))?;
+        OpeningTree::from_records(&board, file.nodes)
+    }
+
+    pub fn load_polyglot(file_name: &str, max_ply: usize) -> std::result::Result<Self, TreeFileError> {
+        Ok(PolyglotBook::load(file_name)?.to_tree(&PolyglotKeys::standard(), &Board::default(), max_ply))
+    }
+
+    pub fn save_polyglot(&self, file_name: &str) -> std::result::Result<(), TreeFileError> {
+        PolyglotBook::from_tree(self, &PolyglotKeys::standard()).save(file_name)
+    }
+
+    fn to_records(&self) -> Vec<NodeRecord> {
+        let uci = |chess_move: &ChessMove| String::from_utf8_lossy(&chess_move.uci()).into_owned();
+        let mut records = vec![];
+        let mut queue = std::collections::VecDeque::from([(None, None, self)]);
+        while let Some((parent, chess_move, node)) = queue.pop_front() {
+            let index = records.len();
+            queue.extend(node.children().map(|(m, child)| (Some(index), Some(m), child)));
+            records.push(NodeRecord {
+                parent,
+                uci: chess_move.map(uci),
+                stats: node.stats,
+                choices: node.choices.iter().map(uci).collect(),
+                comment: node.comment.clone(),
+                nags: node.nags.clone()
+            });
+        }
+        records
+    }
+
+    fn from_records(board: &Board, records: Vec<NodeRecord>) -> std::result::Result<Self, TreeFileError> {
+        let parse = |uci: &str, board: &Board| ChessMove::from_uci(uci.as_bytes(), board)
+            .map_err(|e| TreeFileError::InvalidMove(uci.to_owned(), e));
+        let mut nodes: Vec<OpeningTree> = Vec::with_capacity(records.len());
+        let mut links = Vec::with_capacity(records.len());
+        let mut choices = Vec::with_capacity(records.len());
+        for (index, record) in records.into_iter().enumerate() {
+            let link = match (record.parent, record.uci) {
+                (None, None) if index == 0 => None,
+                (Some(parent), Some(uci)) if parent < index => {
+                    let chess_move = parse(&uci, &nodes[parent].position)?;
+                    Some((parent, chess_move))
+                }
+                _ => return Err(TreeFileError::Corrupt("node without a valid parent"))
+            };
+            let mut position = link.map_or(*board, |(parent, _)| nodes[parent].position);
+            if let Some((_, chess_move)) = &link {
+                position.do_move(chess_move);
+            }
+            let mut tree = OpeningTree::new_from_position(&position);
+            tree.stats = record.stats;
+            tree.comment = record.comment;
+            tree.nags = record.nags;
+            nodes.push(tree);
+            links.push(link);
+            choices.push(record.choices);
+        }
+        // Children always come after their parent, so walking backwards attaches
+        // every node before its parent is itself attached.
+        let mut children: Vec<Vec<(ChessMove, OpeningTree)>> = (0..nodes.len()).map(|_| vec![]).collect();
+        while let Some(mut tree) = nodes.pop() {
+            for (chess_move, child) in children.pop().unwrap().into_iter().rev() {
+                tree.insert_child(chess_move, child);
+            }
+            for uci in choices.pop().unwrap() {
+                let chess_move = parse(&uci, &tree.position)?;
+                if !tree.root.contains_key(&chess_move) {return Err(TreeFileError::Corrupt("choice without a matching move"))}
+                tree.choices.push(chess_move);
+            }
+            match links.pop().unwrap() {
+                Some((parent, chess_move)) => children[parent].push((chess_move, tree)),
+                None => return Ok(tree)
+            }
+        }
+        Err(TreeFileError::Corrupt("no root node"))
+    }
 
     // pub fn add_d4(&mut self) {
     //     self.root = Some(vec!((ChessMove::new(Piece{piece_type: PAWN, color: WHITE}, "d2", "d4"), vec!())));
@@ -46,11 +386,248 @@ impl OpeningTree {
         self.root.iter().fold(vec![], |mut acc, (_, variant)| {acc.append(&mut variant.get_leafs()); acc})
     }
 
+    pub fn from_game(game: &Game) -> Self {
+        let mut tree = OpeningTree::new_from_position(&game.initial_board());
+        tree.add_line(&game.moves());
+        tree
+    }
+
     pub fn add_move(&mut self, chess_move: &ChessMove) {
-        if !self.root.contains_key(&chess_move) {
+        if !self.root.contains_key(chess_move) {
             let mut board = self.position;
             board.do_move(chess_move);
-            self.root.insert(*chess_move, OpeningTree::new_from_position(&board));
+            self.insert_child(*chess_move, OpeningTree::new_from_position(&board));
+        }
+    }
+
+    pub fn add_game(&mut self, game: &Game, max_ply: usize) -> bool {
+        if game.initial_board().hash() != self.position.hash() {return false}
+        let headers = game.headers();
+        let year = headers.date().or(headers.event_date()).and_then(|date| date.year);
+        let result = game.result();
+        self.stats.record(result, None, year);
+        let mut node = self;
+        for (before, chess_move, _) in game.mainline_plies().take(max_ply) {
+            let elo = match before.turn() {
+                Color::WHITE => headers.white_elo(),
+                Color::BLACK => headers.black_elo()
+            }.filter(|&elo| elo > 0);
+            node.add_move(&chess_move);
+            node = node.root.get_mut(&chess_move).unwrap();
+            node.stats.record(result, elo, year);
+        }
+        true
+    }
+
+    pub fn from_game_variations(game: &Game) -> Self {
+        let mut tree = OpeningTree::new_from_position(&game.initial_board());
+        tree.add_variation(game.mainline(), None);
+        tree
+    }
+
+    // Sidelines are added right after the move they replace, so with `chooser`
+    // set the mainline move is ranked first among our choices. Moves marked
+    // as mistakes or dubious are kept in the tree but never chosen.
+    pub(crate) fn add_variation(&mut self, variation: &Variation, chooser: Option<Color>) {
+        let mut node = self;
+        for (i, move_node) in variation.nodes.iter().enumerate() {
+            let chess_move = &move_node.chess_move;
+            let is_bad = move_node.nags.iter().any(|nag| matches!(nag, 2 | 4 | 6));
+            if chooser == Some(node.position.turn()) && !is_bad && !node.is_chosen(chess_move) {
+                node.choices.push(*chess_move);
+            }
+            node.add_move(chess_move);
+            for sideline in &move_node.variations {
+                node.add_variation(sideline, chooser);
+            }
+            let child = node.root.get_mut(chess_move).unwrap();
+            if i == 0 {
+                if let Some(comment) = &variation.comment {
+                    game::add_comment(&mut child.comment, comment);
+                }
+            }
+            if let Some(comment) = &move_node.comment {
+                game::add_comment(&mut child.comment, comment);
+            }
+            for nag in &move_node.nags {
+                if !child.nags.contains(nag) {child.nags.push(*nag)}
+            }
+            node = child;
         }
     }
-}
\ No newline at end of file
+
+    pub fn to_game(&self) -> Game {
+        let mut game = Game::from_position(&self.position);
+        game.mainline = self.to_variation();
+        game
+    }
+
+    pub fn to_pgn(&self) -> String {
+        self.to_game().to_pgn()
+    }
+
+    // Designated moves come first, then the others from most to least played;
+    // the first one continues the line and the rest become its variations.
+    fn ordered_children(&self) -> Vec<(&ChessMove, &OpeningTree)> {
+        let mut children: Vec<(&ChessMove, &OpeningTree)> = self.choices.iter()
+            .filter_map(|m| self.root.get_key_value(m))
+            .collect();
+        children.extend(self.children_by_popularity().into_iter().filter(|(m, _)| !self.is_chosen(m)));
+        children
+    }
+
+    fn to_move_node(&self, chess_move: &ChessMove) -> MoveNode {
+        let mut move_node = MoveNode::new(*chess_move);
+        move_node.nags = self.nags.clone();
+        if let Some(comment) = &self.comment {
+            game::add_comment(&mut move_node.comment, comment);
+        }
+        if self.stats.games > 0 {
+            game::add_comment(&mut move_node.comment, &self.stats.to_string());
+        }
+        move_node
+    }
+
+    fn to_variation(&self) -> Variation {
+        let mut variation = Variation::default();
+        let mut node = self;
+        while let Some(((chess_move, child), alternatives)) = node.ordered_children().split_first() {
+            let mut move_node = child.to_move_node(chess_move);
+            for (alternative, alt_child) in alternatives {
+                let mut sideline = alt_child.to_variation();
+                sideline.nodes.insert(0, alt_child.to_move_node(alternative));
+                move_node.variations.push(sideline);
+            }
+            variation.nodes.push(move_node);
+            node = child;
+        }
+        variation
+    }
+
+    pub fn add_line(&mut self, moves: &[ChessMove]) {
+        if let Some((chess_move, rest)) = moves.split_first() {
+            self.add_move(chess_move);
+            self.root.get_mut(chess_move).unwrap().add_line(rest);
+        }
+    }
+}
+
+impl Default for OpeningTree {
+    fn default() -> Self {
+        OpeningTree::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAMES: &str = "[White \"A\"]
+[Black \"B\"]
+[Date \"1890.01.01\"]
+[WhiteElo \"2400\"]
+[Result \"1-0\"]
+
+1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 1-0
+
+[White \"C\"]
+[Black \"D\"]
+[Date \"1895.??.??\"]
+[BlackElo \"2300\"]
+[Result \"1/2-1/2\"]
+
+1. d4 Nf6 2. c4 e6 3. Nc3 Bb4 1/2-1/2
+
+[White \"E\"]
+[Black \"F\"]
+[Result \"0-1\"]
+
+1. e4 c5 2. Nf3 d6 0-1
+";
+
+    fn same_tree(t1: &OpeningTree, t2: &OpeningTree) -> bool {
+        t1.position.to_fen() == t2.position.to_fen() && t1.stats == t2.stats && t1.choices == t2.choices
+            && t1.comment == t2.comment && t1.nags == t2.nags && t1.root.len() == t2.root.len()
+            && t1.root.iter().all(|(m, c1)| t2.root.get(m).is_some_and(|c2| same_tree(c1, c2)))
+    }
+
+    fn round_trip(tree: &OpeningTree) -> OpeningTree {
+        let mut buf = vec![];
+        tree.write_to(&mut buf).unwrap();
+        OpeningTree::read_from(buf.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn json_round_trip_with_statistics() {
+        let games = Parser::from_reader(GAMES.as_bytes()).map(|game| game.unwrap());
+        let tree = OpeningTree::from_games(games, 10);
+        assert_eq!(tree.stats.games, 3);
+        assert!(same_tree(&tree, &round_trip(&tree)));
+    }
+
+    #[test]
+    fn json_round_trip_with_annotations() {
+        let pgn = "1. e4 {Main} e5 (1... c5 $2 2. Nf3) 2. Nf3 $1 (2. Bc4 $6) *";
+        let tree = Repertoire::from_pgn(WHITE, pgn).unwrap().into_tree();
+        assert!(!tree.choices.is_empty());
+        assert!(same_tree(&tree, &round_trip(&tree)));
+    }
+
+    #[test]
+    fn json_round_trip_of_a_deep_line() {
+        let mut tree = OpeningTree::new();
+        let mut board = tree.position;
+        let mut line = vec![];
+        for uci in ["g1f3", "g8f6", "f3g1", "f6g8"].iter().cycle().take(80) {
+            let chess_move = ChessMove::from_uci(uci.as_bytes(), &board).unwrap();
+            board.do_move(&chess_move);
+            line.push(chess_move);
+        }
+        tree.add_line(&line);
+        tree.node_mut(&line[..79]).unwrap().set_comment(Some(String::from("deep")));
+        let loaded = round_trip(&tree);
+        assert!(same_tree(&tree, &loaded));
+        assert_eq!(loaded.node(&line[..79]).unwrap().comment(), Some("deep"));
+    }
+
+    #[test]
+    fn json_loads_version_1() {
+        let v1 = r#"{"version":1,"position":"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "stats":{"games":2},"choices":["d2d4"],"children":[
+                {"move":"e2e4","stats":{"games":2,"white_wins":1},"children":[{"move":"e7e5","children":[]}]},
+                {"move":"d2d4","comment":"main","children":[]}]}"#;
+        let tree = OpeningTree::read_from(v1.as_bytes()).unwrap();
+        assert_eq!(tree.stats.games, 2);
+        assert!(tree.to_pgn().contains("1. d4 {main} (1. e4"));
+        let (_, child) = tree.children().next().unwrap();
+        assert_eq!(child.stats.white_wins, 1);
+        assert_eq!(child.children().count(), 1);
+        assert!(child.choices.is_empty() && child.comment.is_none() && child.nags.is_empty());
+    }
+
+    #[test]
+    fn json_rejects_bad_parents() {
+        let file = |nodes: &str| format!(r#"{{"version":2,"position":"8/8/8/8/8/8/8/k6K w - - 0 1","nodes":{}}}"#, nodes);
+        let read = |nodes: &str| OpeningTree::read_from(file(nodes).as_bytes());
+        assert!(read(r#"[{}, {"parent":0,"move":"h1h2"}]"#).is_ok());
+        assert!(matches!(read(r#"[{}, {"parent":1,"move":"h1h2"}]"#), Err(TreeFileError::Corrupt(_))));
+        assert!(matches!(read(r#"[{}, {"move":"h1h2"}]"#), Err(TreeFileError::Corrupt(_))));
+        assert!(matches!(read("[]"), Err(TreeFileError::Corrupt(_))));
+    }
+
+    #[test]
+    fn json_rejects_unknown_versions() {
+        let file = |version: &str| format!(r#"{{"version":{},"position":"8/8/8/8/8/8/8/k6K w - - 0 1"}}"#, version);
+        assert!(matches!(OpeningTree::read_from(file("4294967297").as_bytes()), Err(TreeFileError::UnsupportedVersion(4294967297))));
+        assert!(matches!(OpeningTree::read_from(file("3").as_bytes()), Err(TreeFileError::UnsupportedVersion(3))));
+        assert!(matches!(OpeningTree::read_from(&b"{}"[..]), Err(TreeFileError::MissingVersion)));
+    }
+
+    #[test]
+    fn export_keeps_import_order() {
+        let tree = Repertoire::from_pgn(WHITE, "1. e4 e5 (1... c5) (1... e6) 2. Nf3 *").unwrap().into_tree();
+        let expected = "1. e4 e5 (1... c5) (1... e6) 2. Nf3";
+        assert!(tree.to_pgn().replace('\n', " ").contains(expected));
+        assert!(round_trip(&tree).to_pgn().replace('\n', " ").contains(expected));
+    }
+}
@@ -1,15 +1,533 @@
-use std::{io::BufReader, fs::File};
+use crate::{Board, ChessMove, FenError, Game, Headers, MoveNode, NotationError, Variation, game::add_comment};
+use std::{io::{self, BufRead, BufReader}, fs::File, fmt, iter::Peekable, str::Chars};
+use std::collections::BTreeMap;
 
-pub struct Parser {
-    reader: BufReader<File>
+pub struct Parser<R = BufReader<File>> {
+    reader: R,
+    file: Option<String>,
+    line_number: usize,
+    pending: Option<String>,
+    summary: ImportSummary,
+    failed: bool
+}
+
+#[derive(Debug)]
+pub enum PgnErrorKind {
+    Io(io::Error),
+    MalformedTag(String),
+    InvalidMove(String, NotationError),
+    UnexpectedToken(String),
+    InvalidFen(String, FenError),
+    MissingFen,
+    UnterminatedComment,
+    UnclosedVariation,
+    MissingMovetext
+}
+
+impl PgnErrorKind {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            PgnErrorKind::Io(_) => "io error",
+            PgnErrorKind::MalformedTag(_) => "malformed tag",
+            PgnErrorKind::InvalidMove(_, NotationError::InvalidSyntax) => "invalid move notation",
+            PgnErrorKind::InvalidMove(_, NotationError::IllegalMove) => "illegal move",
+            PgnErrorKind::InvalidMove(_, NotationError::AmbiguousMove) => "ambiguous move",
+            PgnErrorKind::UnexpectedToken(_) => "unexpected token",
+            PgnErrorKind::InvalidFen(_, _) => "invalid FEN",
+            PgnErrorKind::MissingFen => "SetUp without FEN",
+            PgnErrorKind::UnterminatedComment => "unterminated comment",
+            PgnErrorKind::UnclosedVariation => "unclosed variation",
+            PgnErrorKind::MissingMovetext => "tags without movetext"
+        }
+    }
+}
+
+impl fmt::Display for PgnErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PgnErrorKind::Io(e) => write!(f, "{}", e),
+            PgnErrorKind::MalformedTag(tag) => write!(f, "malformed tag: {}", tag),
+            PgnErrorKind::InvalidMove(notation, e) => write!(f, "{}: {}", e, notation),
+            PgnErrorKind::UnexpectedToken(token) => write!(f, "unexpected token: {}", token),
+            PgnErrorKind::InvalidFen(fen, e) => write!(f, "invalid FEN ({}): {}", e, fen),
+            PgnErrorKind::MissingFen | PgnErrorKind::UnterminatedComment | PgnErrorKind::UnclosedVariation
+                | PgnErrorKind::MissingMovetext => write!(f, "{}", self.reason())
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PgnError {
+    pub kind: PgnErrorKind,
+    pub file: Option<String>,
+    pub line: usize,
+    pub column: usize
+}
+
+impl PgnError {
+    fn new(kind: PgnErrorKind, (line, column): (usize, usize)) -> Self {
+        PgnError {kind, file: None, line, column}
+    }
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        write!(f, "{}:{}: {}", self.line, self.column, self.kind)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ImportSummary {
+    pub games: usize,
+    pub skipped: usize,
+    pub reasons: BTreeMap<&'static str, usize>
+}
+
+impl fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} games imported, {} skipped", self.games, self.skipped)?;
+        for (reason, count) in &self.reasons {
+            write!(f, "\n  {}: {}", reason, count)?;
+        }
+        Ok(())
+    }
+}
+
+type Position = (usize, usize);
+
+enum Token {
+    Symbol(String),
+    Result(Option<crate::Result>),
+    Comment(String),
+    Nag(u8),
+    StartVariation,
+    EndVariation
+}
+
+struct Cursor<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize
+}
+
+impl Cursor<'_> {
+    fn position(&self) -> Position {
+        (self.line, self.column)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        }
+        else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn next_if(&mut self, func: impl FnOnce(&char) -> bool) -> Option<char> {
+        match self.chars.peek() {
+            Some(c) if func(c) => self.next(),
+            _ => None
+        }
+    }
+
+    fn take_until(&mut self, end: char) -> Option<String> {
+        let mut text = String::new();
+        while let Some(c) = self.next() {
+            if c == end {return Some(text)}
+            text.push(c);
+        }
+        None
+    }
+}
+
+struct Level {
+    variation: Variation,
+    board: Board,
+    previous: Board,
+    start: Position
+}
+
+struct RawGame {
+    first_line: usize,
+    lines: Vec<String>
 }
 
 impl Parser {
     pub fn new(filename: &str) -> Result<Self, &str> {
         let f = File::open(filename);
         match f {
-            Ok(f) => Ok(Parser {reader: BufReader::new(f)}),
+            Ok(f) => {
+                let mut parser = Parser::from_reader(BufReader::new(f));
+                parser.file = Some(filename.to_string());
+                Ok(parser)
+            }
             _ => Err("unknown database")
         }
     }
-}
\ No newline at end of file
+}
+
+impl<R: BufRead> Parser<R> {
+    pub fn from_reader(reader: R) -> Self {
+        Parser {reader, file: None, line_number: 0, pending: None, summary: ImportSummary::default(), failed: false}
+    }
+
+    pub fn summary(&self) -> &ImportSummary {
+        &self.summary
+    }
+
+    fn next_line(&mut self) -> Option<io::Result<String>> {
+        if let Some(line) = self.pending.take() {
+            self.line_number += 1;
+            return Some(Ok(line));
+        }
+        let mut buffer = Vec::<u8>::new();
+        match self.reader.read_until(b'\n', &mut buffer) {
+            Ok(0) => None,
+            Ok(_) => {
+                self.line_number += 1;
+                let line = String::from_utf8_lossy(&buffer).into_owned();
+                if line.starts_with('%') {Some(Ok(String::from("\n")))} else {Some(Ok(line))}
+            }
+            Err(e) => Some(Err(e))
+        }
+    }
+
+    fn read_game(&mut self) -> Option<io::Result<RawGame>> {
+        let mut game = RawGame {first_line: 0, lines: Vec::<String>::new()};
+        let mut in_movetext = false;
+        let mut in_comment = false;
+        let mut after_blank = false;
+        while let Some(line) = self.next_line() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e))
+            };
+            let trimmed = line.trim().trim_start_matches('\u{feff}');
+            if game.lines.is_empty() {
+                if trimmed.is_empty() {continue}
+                game.first_line = self.line_number;
+            }
+            let is_tag = looks_like_tag(trimmed);
+            // Comments may wrap onto lines starting with `[` (`[%clk ...]`), so
+            // inside one only a well-formed tag after a blank line starts the
+            // next game; the unterminated comment is then reported on its own.
+            let next_game = if in_comment {after_blank && parse_tag(trimmed, 0).is_ok()} else {is_tag};
+            // A second tag section without movetext in between also starts
+            // a game, and the first one is reported as missing its movetext.
+            if next_game && (in_movetext || after_blank) {
+                self.pending = Some(line);
+                self.line_number -= 1;
+                break;
+            }
+            in_movetext |= !is_tag && !trimmed.is_empty();
+            if in_movetext {
+                in_comment = comment_open_after(trimmed, in_comment);
+            }
+            after_blank = trimmed.is_empty();
+            game.lines.push(line);
+        }
+        if game.lines.is_empty() {None} else {Some(Ok(game))}
+    }
+}
+
+impl<R: BufRead> Iterator for Parser<R> {
+    type Item = Result<Game, PgnError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {return None}
+        let game = match self.read_game()? {
+            Ok(game) => parse_game(&game),
+            Err(e) => {
+                self.failed = true;
+                Err(PgnError::new(PgnErrorKind::Io(e), (self.line_number+1, 1)))
+            }
+        };
+        match game {
+            Ok(game) => {
+                self.summary.games += 1;
+                Some(Ok(game))
+            }
+            Err(mut e) => {
+                self.summary.skipped += 1;
+                *self.summary.reasons.entry(e.kind.reason()).or_insert(0) += 1;
+                e.file = self.file.clone();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+// Whether a `{...}` comment is still open at the end of `line`.
+fn comment_open_after(line: &str, mut in_comment: bool) -> bool {
+    for c in line.chars() {
+        match c {
+            '}' if in_comment => in_comment = false,
+            '{' if !in_comment => in_comment = true,
+            ';' if !in_comment => break,
+            _ => ()
+        }
+    }
+    in_comment
+}
+
+fn looks_like_tag(line: &str) -> bool {
+    line.starts_with('[')
+        || line.ends_with(']') && line.contains('"') && line.starts_with(|c: char| c.is_ascii_alphabetic())
+}
+
+fn parse_tag(line: &str, line_number: usize) -> Result<(String, String), PgnError> {
+    let column = line.len() - line.trim_start().len() + 1;
+    let malformed = || PgnError::new(PgnErrorKind::MalformedTag(line.trim().to_string()), (line_number, column));
+    let inner = line.trim().strip_prefix('[').and_then(|l| l.strip_suffix(']')).ok_or_else(malformed)?;
+    let (name, value) = inner.split_once(char::is_whitespace).ok_or_else(malformed)?;
+    let value = value.trim().strip_prefix('"').and_then(|v| v.strip_suffix('"')).ok_or_else(malformed)?;
+    Ok((name.to_string(), value.replace("\\\"", "\"").replace("\\\\", "\\")))
+}
+
+fn glyph_to_nag(glyph: &str) -> Option<u8> {
+    match glyph {
+        "!" => Some(1),
+        "?" => Some(2),
+        "!!" => Some(3),
+        "??" => Some(4),
+        "!?" => Some(5),
+        "?!" => Some(6),
+        _ => None
+    }
+}
+
+fn parse_result(symbol: &str) -> Option<Option<crate::Result>> {
+    match symbol {
+        "1-0" => Some(Some(crate::Result::White)),
+        "0-1" => Some(Some(crate::Result::Black)),
+        "1/2-1/2" => Some(Some(crate::Result::Draw)),
+        "*" => Some(None),
+        _ => None
+    }
+}
+
+fn tokenize(movetext: &str, first_line: usize) -> Result<Vec<(Position, Token)>, PgnError> {
+    let mut tokens = Vec::<(Position, Token)>::new();
+    let mut cursor = Cursor {chars: movetext.chars().peekable(), line: first_line, column: 1};
+    loop {
+        let position = cursor.position();
+        let c = match cursor.next() {
+            Some(c) => c,
+            None => break
+        };
+        match c {
+            '{' => {
+                let comment = cursor.take_until('}')
+                    .ok_or_else(|| PgnError::new(PgnErrorKind::UnterminatedComment, position))?;
+                tokens.push((position, Token::Comment(comment.split_whitespace().collect::<Vec<_>>().join(" "))));
+            }
+            ';' => {
+                let comment = cursor.take_until('\n').unwrap_or_default();
+                tokens.push((position, Token::Comment(comment.trim().to_string())));
+            }
+            '(' => tokens.push((position, Token::StartVariation)),
+            ')' => tokens.push((position, Token::EndVariation)),
+            '$' => {
+                let mut nag = String::new();
+                while let Some(c) = cursor.next_if(|c| c.is_ascii_digit()) {
+                    nag.push(c);
+                }
+                let nag = nag.parse().map_err(|_| PgnError::new(PgnErrorKind::UnexpectedToken(format!("${}", nag)), position))?;
+                tokens.push((position, Token::Nag(nag)));
+            }
+            c if c.is_whitespace() => (),
+            c => {
+                let mut symbol = String::from(c);
+                while let Some(c) = cursor.next_if(|c| !c.is_whitespace() && !"{}();$".contains(*c)) {
+                    symbol.push(c);
+                }
+                match parse_result(&symbol) {
+                    Some(result) => tokens.push((position, Token::Result(result))),
+                    None => {
+                        let san = symbol.trim_start_matches(|c: char| c.is_ascii_digit()).trim_start_matches('.');
+                        let san = if san.len() < symbol.len() && !symbol.starts_with("0-0") {san} else {&symbol};
+                        if !san.is_empty() {
+                            let column = position.1 + symbol.len() - san.len();
+                            tokens.push(((position.0, column), Token::Symbol(san.to_string())));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_game(raw_game: &RawGame) -> Result<Game, PgnError> {
+    let mut game = Game::new();
+    let mut result_tag = None;
+    let mut setup_line = None;
+    let mut fen_line = None;
+    let mut movetext = String::new();
+    let mut movetext_line = raw_game.first_line;
+    for (i, line) in raw_game.lines.iter().enumerate() {
+        let line_number = raw_game.first_line + i;
+        if movetext.is_empty() && (line.trim().is_empty() || looks_like_tag(line.trim())) {
+            if line.trim().is_empty() {continue}
+            let (name, value) = parse_tag(line, line_number)?;
+            match Headers::normalize_name(&name) {
+                "Result" => result_tag = parse_result(&value),
+                "SetUp" if value == "1" => setup_line = Some(line_number),
+                "FEN" => fen_line = Some(line_number),
+                _ => ()
+            }
+            game.headers.set(&name, &value);
+        }
+        else {
+            if movetext.is_empty() {
+                movetext_line = line_number;
+            }
+            movetext.push_str(line);
+        }
+    }
+    if movetext.trim().is_empty() {
+        return Err(PgnError::new(PgnErrorKind::MissingMovetext, (raw_game.first_line, 1)));
+    }
+    match (game.headers.get("FEN"), fen_line, setup_line) {
+        (Some(fen), Some(line), _) => {
+            game.initial_board = Board::try_from_fen(fen.as_bytes())
+                .map_err(|e| PgnError::new(PgnErrorKind::InvalidFen(fen.to_string(), e), (line, 1)))?;
+        }
+        (_, _, Some(line)) => return Err(PgnError::new(PgnErrorKind::MissingFen, (line, 1))),
+        _ => ()
+    }
+    let board = game.initial_board;
+    let mut levels = vec![Level {variation: Variation::default(), board, previous: board, start: (movetext_line, 1)}];
+    let mut result_token = None;
+    for (position, token) in tokenize(&movetext, movetext_line)? {
+        let unexpected = |token: &str| Err(PgnError::new(PgnErrorKind::UnexpectedToken(token.to_string()), position));
+        let depth = levels.len();
+        let level = levels.last_mut().unwrap();
+        match token {
+            Token::StartVariation => {
+                if level.variation.nodes.is_empty() {return unexpected("(")}
+                let board = level.previous;
+                levels.push(Level {variation: Variation::default(), board, previous: board, start: position});
+            }
+            Token::EndVariation => {
+                if depth == 1 {return unexpected(")")}
+                let variation = levels.pop().unwrap().variation;
+                if !variation.nodes.is_empty() {
+                    levels.last_mut().unwrap().variation.nodes.last_mut().unwrap().variations.push(variation);
+                }
+            }
+            Token::Comment(comment) => match level.variation.nodes.last_mut() {
+                Some(node) => add_comment(&mut node.comment, &comment),
+                None => add_comment(&mut level.variation.comment, &comment)
+            },
+            Token::Nag(nag) => match level.variation.nodes.last_mut() {
+                Some(node) => node.nags.push(nag),
+                None => return unexpected(&format!("${}", nag))
+            },
+            Token::Result(_) if depth > 1 => return Err(PgnError::new(PgnErrorKind::UnclosedVariation, levels[1].start)),
+            Token::Result(result) => result_token = Some(result),
+            Token::Symbol(symbol) if result_token.is_some() => return unexpected(&symbol),
+            Token::Symbol(symbol) => {
+                let glyphs = symbol.len() - symbol.trim_end_matches(['!', '?']).len();
+                let (san, glyph) = symbol.split_at(symbol.len() - glyphs);
+                let chess_move = ChessMove::from_notation(san.as_bytes(), &level.board)
+                    .map_err(|e| PgnError::new(PgnErrorKind::InvalidMove(symbol.clone(), e), position))?;
+                level.previous = level.board;
+                level.board.do_move(&chess_move);
+                let mut node = MoveNode::new(chess_move);
+                if let Some(nag) = glyph_to_nag(glyph) {
+                    node.nags.push(nag);
+                }
+                level.variation.nodes.push(node);
+            }
+        }
+    }
+    if levels.len() > 1 {
+        return Err(PgnError::new(PgnErrorKind::UnclosedVariation, levels[1].start));
+    }
+    game.mainline = levels.pop().unwrap().variation;
+    game.result = result_token.or(result_tag).flatten();
+    Ok(game)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(pgn: &str) -> (Vec<Result<Game, PgnError>>, ImportSummary) {
+        let mut parser = Parser::from_reader(pgn.as_bytes());
+        let games = parser.by_ref().collect();
+        (games, parser.summary().clone())
+    }
+
+    #[test]
+    fn games_are_split_at_tags() {
+        let (games, summary) = parse("[White \"A\"]\n\n1. e4 e5 *\n[White \"B\"]\n\n1. d4 d5 1-0\n");
+        assert_eq!(summary.games, 2);
+        let game = games[1].as_ref().unwrap();
+        assert_eq!(game.headers.get("White"), Some("B"));
+        assert_eq!(game.result(), Some(crate::Result::White));
+    }
+
+    #[test]
+    fn comments_may_wrap_onto_bracketed_lines() {
+        let pgn = "[White \"A\"]\n\n1. e4 {long comment\n[see diagram] here} e5 { [%clk 0:03:00]\n[%eval 0.3] } 2. Nf3 ; rest {\n[White \"B\"]\n\n1. d4 *\n";
+        let (games, summary) = parse(pgn);
+        assert_eq!((summary.games, summary.skipped), (2, 0));
+        let game = games[0].as_ref().unwrap();
+        assert_eq!(game.plies().count(), 3);
+        assert_eq!(game.mainline.nodes[0].comment.as_deref(), Some("long comment [see diagram] here"));
+    }
+
+    #[test]
+    fn unterminated_comment_does_not_swallow_the_next_game() {
+        let (games, summary) = parse("1. e4 {oops e5 *\n\n[White \"B\"]\n\n1. d4 *\n");
+        assert_eq!((summary.games, summary.skipped), (1, 1));
+        assert!(matches!(games[0].as_ref().unwrap_err().kind, PgnErrorKind::UnterminatedComment));
+        assert_eq!(games[1].as_ref().unwrap().headers.get("White"), Some("B"));
+    }
+
+    #[test]
+    fn tags_without_movetext_are_reported() {
+        let (games, summary) = parse("[White \"A\"]\n[Black \"C\"]\n\n[White \"B\"]\n\n1. d4 *\n");
+        assert_eq!((summary.games, summary.skipped), (1, 1));
+        let error = games[0].as_ref().unwrap_err();
+        assert!(matches!(error.kind, PgnErrorKind::MissingMovetext));
+        assert_eq!((error.line, error.column), (1, 1));
+        let game = games[1].as_ref().unwrap();
+        assert_eq!(game.headers.get("White"), Some("B"));
+        assert_eq!(game.headers.get("Black"), None);
+    }
+
+    #[test]
+    fn unclosed_variation_is_reported_at_its_start() {
+        let (games, _) = parse("1. e4 (1. d4 *\n");
+        let error = games[0].as_ref().unwrap_err();
+        assert!(matches!(error.kind, PgnErrorKind::UnclosedVariation));
+        assert_eq!((error.line, error.column), (1, 7));
+        let (games, _) = parse("1. e4 (1. d4\n");
+        assert!(matches!(games[0].as_ref().unwrap_err().kind, PgnErrorKind::UnclosedVariation));
+    }
+
+    #[test]
+    fn errors_are_located_and_import_continues() {
+        let pgn = "[Event \"1\"]\n\n1. e4 e5 2. Ke3 *\n\n[Event \"2\"]\n\n1. d4 $x *\n\n[Event \"3\"\n\n1. c4 *\n\n\
+                   [Event \"4\"]\n[SetUp \"1\"]\n\n1. e4 *\n\n[Event \"5\"]\n\n1. Nf3 Nf6 1/2-1/2\n";
+        let mut parser = Parser::from_reader(pgn.as_bytes());
+        let errors: Vec<PgnError> = parser.by_ref().filter_map(|game| game.err()).collect();
+        let located: Vec<(&str, usize, usize)> = errors.iter().map(|e| (e.kind.reason(), e.line, e.column)).collect();
+        assert_eq!(located, vec![("illegal move", 3, 13), ("unexpected token", 7, 7), ("malformed tag", 9, 1), ("SetUp without FEN", 14, 1)]);
+        assert_eq!(errors[0].to_string(), "3:13: illegal move: Ke3");
+        let summary = parser.summary();
+        assert_eq!((summary.games, summary.skipped), (1, 4));
+        assert_eq!(summary.reasons["illegal move"], 1);
+        assert_eq!(summary.to_string().lines().next(), Some("1 games imported, 4 skipped"));
+    }
+}
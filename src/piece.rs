@@ -1,6 +1,7 @@
 use crate::*;
 use serde::{Serialize, Deserialize};
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum PieceType {
     KING,
@@ -46,12 +47,12 @@ impl PieceType {
 
     pub fn get_char(&self) -> u8 {
         match self {
-            PAWN => 'P' as u8,
-            KNIGHT => 'N' as u8,
-            BISHOP => 'B' as u8,
-            ROOK => 'R' as u8,
-            QUEEN => 'Q' as u8,
-            KING => 'K' as u8
+            PAWN => b'P',
+            KNIGHT => b'N',
+            BISHOP => b'B',
+            ROOK => b'R',
+            QUEEN => b'Q',
+            KING => b'K'
         }
     }
 
@@ -60,11 +61,7 @@ impl PieceType {
 impl Piece {
     pub fn from_char(c : &u8) -> Option<Self> {
         let color = if (*c as char).is_uppercase() {WHITE} else {BLACK};
-        let piece_type = PieceType::from_char(c);
-        match piece_type {
-            Some(p) => Some(Piece {piece_type: p, color}),
-            None => None
-        }
+        PieceType::from_char(c).map(|piece_type| Piece {piece_type, color})
     } 
 
     pub fn get_notation(&self) -> Option<u8> {
@@ -105,138 +102,138 @@ pub trait CanMove {
     fn available_moves(board: &Board, coord: &Coord, color: &Color) -> Vec<ChessMove>; 
 }
 
-const PAWN_CAPTURE_DIRS: [Coord; 2] = [Coord(0,1), Coord(0,-1)];
-
-// impl CanMove for Pawn {
-//     fn available_moves(board: &Board, coord: &Coord, color: &Color) -> Vec<ChessMove> {
-//         let mut moves = Vec::<ChessMove>::new();
-//         let dir: Coord = match color {
-//             Color::WHITE => Coord(1, 0),
-//             Color::BLACK => Coord(-1, 0)
-//         };
-//         let new_coord = *coord+dir;
-//         if board.is_square_free(&new_coord) {
-//             moves.push(ChessMove {piece: Piece{piece_type: PAWN, color: *color}, start: *coord, end: new_coord});
-//             if *color==WHITE && coord.0==1 || *color==BLACK && coord.0==7 {
-//                 let new_coord = *coord+dir*2;
-//                 if board.is_square_free(&new_coord) {
-//                     moves.push(ChessMove {piece: Piece{piece_type: PAWN, color: *color}, start: *coord, end: new_coord});
-//                 }
-//             }
-//         }
-//         for cap_dir in PAWN_CAPTURE_DIRS {
-//             let new_coord = *coord+dir+cap_dir;
-//             if board.can_capture(&new_coord, &color) {
-//                 moves.push(ChessMove {piece: Piece{piece_type: PAWN, color: *color}, start: *coord, end: new_coord});
-//             }
-//             if Some(new_coord) == board.en_passant {
-//                 moves.push(ChessMove {piece: Piece{piece_type: PAWN, color: *color}, start: *coord, end: new_coord});
-//             }
-//         }
-//         moves
-//     }
-// }
-
-// const KNIGHT_DIRS: [Coord; 8] = [Coord(1,2), Coord(1,-2), Coord(2,1), Coord(2,-1), Coord(-1,2), Coord(-1,-2), Coord(-2,1), Coord(-2,-1)];
-
-// impl CanMove for Knight {
-    
-//     fn available_moves(board: &Board, coord: &Coord, color: &Color) -> Vec<ChessMove> {
-//         let mut moves = Vec::<ChessMove>::new();
-//         for dir in KNIGHT_DIRS {
-//             let new_coord = *coord+dir;
-//             if board.is_square_free(&new_coord) || board.can_capture(&new_coord, color) {
-//                 moves.push(ChessMove {piece: Piece{piece_type: KNIGHT, color: *color}, start: *coord, end: new_coord});
-//             }
-//         }
-//         moves
-//     }
-// }
-
-// const BISHOP_DIRS: [Coord; 4] = [Coord(1,1), Coord(1,-1), Coord(-1, 1), Coord(-1, -1)];
-
-// impl CanMove for Bishop {
-
-//     fn available_moves(board: &Board, coord: &Coord, color: &Color) -> Vec<ChessMove> {
-//         let mut moves = Vec::<ChessMove>::new();
-//         for dir in BISHOP_DIRS {
-//             for nb_squares in 1..8 {
-//                 let new_coord = *coord+dir*nb_squares;
-//                 if board.is_square_free(&new_coord) || board.can_capture(&new_coord, color) {
-//                     moves.push(ChessMove {piece: Piece{piece_type: BISHOP, color: *color}, start: *coord, end: new_coord});
-//                 }
-//                 if !board.is_square_free(&new_coord) {
-//                     break;
-//                 }
-//             }
-//         }
-//         moves
-//     }
-// }
-
-// const ROOK_DIRS: [Coord; 4] = [Coord(0,1), Coord(0,-1), Coord(-1, 0), Coord(1, 0)];
-
-// impl CanMove for Rook {
-//     fn available_moves(board: &Board, coord: &Coord, color: &Color) -> Vec<ChessMove> {
-//         let mut moves = Vec::<ChessMove>::new();
-//         for dir in ROOK_DIRS {
-//             for nb_squares in 1..8 {
-//                 let new_coord = *coord+dir*nb_squares;
-//                 if board.is_square_free(&new_coord) || board.can_capture(&new_coord, color) {
-//                     moves.push(ChessMove {piece: Piece{piece_type: ROOK, color: *color}, start: *coord, end: new_coord});
-//                 }
-//                 if !board.is_square_free(&new_coord) {
-//                     break;
-//                 }
-//             }
-//         }
-//         moves
-//     }
-// }
-
-// const QUEEN_DIRS: [Coord; 8] = [Coord(0,1), Coord(0,-1), Coord(-1, 0), Coord(1, 0), Coord(1,1), Coord(1,-1), Coord(-1, 1), Coord(-1, -1)];
-
-// impl CanMove for Queen {
-//     fn available_moves(board: &Board, coord: &Coord, color: &Color) -> Vec<ChessMove> {
-//         let mut moves = Vec::<ChessMove>::new();
-//         for dir in QUEEN_DIRS {
-//             for nb_squares in 1..8 {
-//                 let new_coord = *coord+dir*nb_squares;
-//                 if board.is_square_free(&new_coord) || board.can_capture(&new_coord, color) {
-//                     moves.push(ChessMove {piece: Piece{piece_type: QUEEN, color: *color}, start: *coord, end: new_coord});
-//                 }
-//                 if !board.is_square_free(&new_coord) {
-//                     break;
-//                 }
-//             }
-//         }
-//         moves
-//     }
-// }
-
-// impl CanMove for King {
-//     fn available_moves(board: &Board, coord: &Coord, color: &Color) -> Vec<ChessMove> {
-//         let mut moves = Vec::<ChessMove>::new();
-//         for dir in QUEEN_DIRS {
-//             let new_coord = *coord+dir;
-//             if board.is_square_free(&new_coord) || board.can_capture(&new_coord, color) {
-//                 moves.push(ChessMove {piece: Piece{piece_type: KING, color: *color}, start: *coord, end: new_coord});
-//             }
-//         }
-//         moves
-//     }
-// }
-
-// impl Piece {
-
-//     pub fn available_moves(&self, board: &Board, coord: &Coord) -> Vec<ChessMove> {
-//         match self.piece_type {
-//             PAWN => Pawn::available_moves(board, coord, &self.color),
-//             KNIGHT => Knight::available_moves(board, coord, &self.color),
-//             BISHOP => Bishop::available_moves(board, coord, &self.color),
-//             ROOK => Rook::available_moves(board, coord, &self.color),
-//             QUEEN => Queen::available_moves(board, coord, &self.color),
-//             KING => King::available_moves(board, coord, &self.color)
-//         }
-//     }
-// }
+pub(crate) const PAWN_CAPTURE_DIRS: [Coord; 2] = [Coord(0,1), Coord(0,-1)];
+const PROMOTIONS: [PieceType; 4] = [QUEEN, ROOK, BISHOP, KNIGHT];
+
+fn new_move(board: &Board, piece_type: PieceType, color: &Color, start: &Coord, end: Coord) -> ChessMove {
+    let mut chess_move = ChessMove::new(Piece{piece_type, color: *color}, *start, end);
+    chess_move.is_capture = board.can_capture(&end, color);
+    chess_move
+}
+
+fn push_pawn_move(moves: &mut Vec<ChessMove>, chess_move: ChessMove) {
+    if chess_move.end.0 == 0 || chess_move.end.0 == 7 {
+        for piece_type in PROMOTIONS {
+            let mut promotion = chess_move;
+            promotion.promotion = Some(Piece{piece_type, color: chess_move.piece.color});
+            moves.push(promotion);
+        }
+    }
+    else {
+        moves.push(chess_move);
+    }
+}
+
+impl CanMove for Pawn {
+    fn available_moves(board: &Board, coord: &Coord, color: &Color) -> Vec<ChessMove> {
+        let mut moves = Vec::<ChessMove>::new();
+        let dir: Coord = match color {
+            Color::WHITE => Coord(1, 0),
+            Color::BLACK => Coord(-1, 0)
+        };
+        let new_coord = *coord+dir;
+        if board.is_square_free(&new_coord) {
+            push_pawn_move(&mut moves, new_move(board, PAWN, color, coord, new_coord));
+            if *color==WHITE && coord.0==1 || *color==BLACK && coord.0==6 {
+                let new_coord = *coord+dir*2;
+                if board.is_square_free(&new_coord) {
+                    moves.push(new_move(board, PAWN, color, coord, new_coord));
+                }
+            }
+        }
+        for cap_dir in PAWN_CAPTURE_DIRS {
+            let new_coord = *coord+dir+cap_dir;
+            if board.can_capture(&new_coord, color) {
+                push_pawn_move(&mut moves, new_move(board, PAWN, color, coord, new_coord));
+            }
+            if Some(new_coord) == board.en_passant {
+                let mut chess_move = new_move(board, PAWN, color, coord, new_coord);
+                chess_move.is_capture = true;
+                moves.push(chess_move);
+            }
+        }
+        moves
+    }
+}
+
+pub(crate) const KNIGHT_DIRS: [Coord; 8] = [Coord(1,2), Coord(1,-2), Coord(2,1), Coord(2,-1), Coord(-1,2), Coord(-1,-2), Coord(-2,1), Coord(-2,-1)];
+
+impl CanMove for Knight {
+    fn available_moves(board: &Board, coord: &Coord, color: &Color) -> Vec<ChessMove> {
+        let mut moves = Vec::<ChessMove>::new();
+        for dir in KNIGHT_DIRS {
+            let new_coord = *coord+dir;
+            if board.is_square_free(&new_coord) || board.can_capture(&new_coord, color) {
+                moves.push(new_move(board, KNIGHT, color, coord, new_coord));
+            }
+        }
+        moves
+    }
+}
+
+fn sliding_moves(board: &Board, coord: &Coord, color: &Color, piece_type: PieceType, dirs: &[Coord]) -> Vec<ChessMove> {
+    let mut moves = Vec::<ChessMove>::new();
+    for dir in dirs {
+        for nb_squares in 1..8 {
+            let new_coord = *coord+*dir*nb_squares;
+            if board.is_square_free(&new_coord) || board.can_capture(&new_coord, color) {
+                moves.push(new_move(board, piece_type, color, coord, new_coord));
+            }
+            if !board.is_square_free(&new_coord) {
+                break;
+            }
+        }
+    }
+    moves
+}
+
+pub(crate) const BISHOP_DIRS: [Coord; 4] = [Coord(1,1), Coord(1,-1), Coord(-1, 1), Coord(-1, -1)];
+
+impl CanMove for Bishop {
+    fn available_moves(board: &Board, coord: &Coord, color: &Color) -> Vec<ChessMove> {
+        sliding_moves(board, coord, color, BISHOP, &BISHOP_DIRS)
+    }
+}
+
+pub(crate) const ROOK_DIRS: [Coord; 4] = [Coord(0,1), Coord(0,-1), Coord(-1, 0), Coord(1, 0)];
+
+impl CanMove for Rook {
+    fn available_moves(board: &Board, coord: &Coord, color: &Color) -> Vec<ChessMove> {
+        sliding_moves(board, coord, color, ROOK, &ROOK_DIRS)
+    }
+}
+
+pub(crate) const QUEEN_DIRS: [Coord; 8] = [Coord(0,1), Coord(0,-1), Coord(-1, 0), Coord(1, 0), Coord(1,1), Coord(1,-1), Coord(-1, 1), Coord(-1, -1)];
+
+impl CanMove for Queen {
+    fn available_moves(board: &Board, coord: &Coord, color: &Color) -> Vec<ChessMove> {
+        sliding_moves(board, coord, color, QUEEN, &QUEEN_DIRS)
+    }
+}
+
+impl CanMove for King {
+    fn available_moves(board: &Board, coord: &Coord, color: &Color) -> Vec<ChessMove> {
+        let mut moves = Vec::<ChessMove>::new();
+        for dir in QUEEN_DIRS {
+            let new_coord = *coord+dir;
+            if board.is_square_free(&new_coord) || board.can_capture(&new_coord, color) {
+                moves.push(new_move(board, KING, color, coord, new_coord));
+            }
+        }
+        moves.append(&mut board.castling_moves(coord, color));
+        moves
+    }
+}
+
+impl Piece {
+    pub fn available_moves(&self, board: &Board, coord: &Coord) -> Vec<ChessMove> {
+        match self.piece_type {
+            PAWN => Pawn::available_moves(board, coord, &self.color),
+            KNIGHT => Knight::available_moves(board, coord, &self.color),
+            BISHOP => Bishop::available_moves(board, coord, &self.color),
+            ROOK => Rook::available_moves(board, coord, &self.color),
+            QUEEN => Queen::available_moves(board, coord, &self.color),
+            KING => King::available_moves(board, coord, &self.color)
+        }
+    }
+}
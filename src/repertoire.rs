@@ -0,0 +1,335 @@
+use crate::*;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepertoireIssueKind {
+    MissingChoice,
+    ChoiceOnOpponentTurn
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepertoireIssue {
+    pub kind: RepertoireIssueKind,
+    pub line: Vec<ChessMove>,
+    notation: String
+}
+
+impl fmt::Display for RepertoireIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = if self.line.is_empty() {"initial position"} else {&self.notation};
+        match self.kind {
+            RepertoireIssueKind::MissingChoice => write!(f, "no move chosen after {}", line),
+            RepertoireIssueKind::ChoiceOnOpponentTurn => write!(f, "move chosen for the opponent after {}", line)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageGap {
+    pub line: Vec<ChessMove>,
+    pub games: usize,
+    pub share: f64,
+    pub frequency: f64,
+    notation: String
+}
+
+impl fmt::Display for CoverageGap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {:.1}% of games here, {:.1}% overall ({} games)",
+            self.notation, self.share * 100., self.frequency * 100., self.games)
+    }
+}
+
+// On our turns only the chosen moves (best ranked first) belong to the
+// repertoire, on the opponent's turns every reply in the tree does.
+#[derive(Debug)]
+pub struct Repertoire {
+    color: Color,
+    tree: OpeningTree
+}
+
+impl Repertoire {
+    pub fn new(color: Color) -> Self {
+        Repertoire {color, tree: OpeningTree::new()}
+    }
+
+    pub fn from_tree(color: Color, tree: OpeningTree) -> Self {
+        Repertoire {color, tree}
+    }
+
+    pub fn from_game(color: Color, game: &Game) -> Self {
+        let mut repertoire = Repertoire::from_tree(color, OpeningTree::new_from_position(&game.initial_board()));
+        repertoire.add_game(game);
+        repertoire
+    }
+
+    pub fn from_pgn(color: Color, pgn: &str) -> std::result::Result<Self, PgnError> {
+        Ok(Repertoire::from_game(color, &Game::from_pgn(pgn)?))
+    }
+
+    // Merges the mainline and every sideline of `game`; our moves become choices
+    // unless annotated as mistakes or dubious.
+    pub fn add_game(&mut self, game: &Game) -> bool {
+        if game.initial_board().hash() != self.tree.position().hash() {return false}
+        self.tree.add_variation(game.mainline(), Some(self.color));
+        true
+    }
+
+    pub fn color(&self) -> Color {
+        self.color
+    }
+
+    pub fn tree(&self) -> &OpeningTree {
+        &self.tree
+    }
+
+    pub fn into_tree(self) -> OpeningTree {
+        self.tree
+    }
+
+    pub fn is_our_turn(&self, node: &OpeningTree) -> bool {
+        node.position().turn() == self.color
+    }
+
+    pub fn choices(&self, line: &[ChessMove]) -> &[ChessMove] {
+        self.tree.node(line).map_or(&[], |node| node.choices())
+    }
+
+    // Moves built by hand (e.g. with `ChessMove::new`) lack the capture and
+    // check flags, so they are swapped for the matching legal move before
+    // being compared with or stored in the tree.
+    fn legal_move(board: &Board, chess_move: &ChessMove) -> Option<ChessMove> {
+        board.legal_moves().into_iter().find(|m| m.start == chess_move.start && m.end == chess_move.end
+            && m.promotion.map(|p| p.piece_type) == chess_move.promotion.map(|p| p.piece_type))
+    }
+
+    fn legal_line(&self, line: &[ChessMove], chess_move: &ChessMove) -> Option<(Vec<ChessMove>, ChessMove, Color)> {
+        let mut board = *self.tree.position();
+        let mut legal_line = Vec::with_capacity(line.len());
+        for m in line {
+            let m = Repertoire::legal_move(&board, m)?;
+            board.do_move(&m);
+            legal_line.push(m);
+        }
+        Some((legal_line, Repertoire::legal_move(&board, chess_move)?, board.turn()))
+    }
+
+    pub fn add_choice(&mut self, line: &[ChessMove], chess_move: &ChessMove) -> bool {
+        let Some((line, chess_move, turn)) = self.legal_line(line, chess_move) else {return false};
+        if turn != self.color {return false}
+        self.tree.add_line(&line);
+        let node = self.tree.node_mut(&line).unwrap();
+        node.add_move(&chess_move);
+        if !node.is_chosen(&chess_move) {
+            node.choices_mut().push(chess_move);
+        }
+        true
+    }
+
+    pub fn remove_choice(&mut self, line: &[ChessMove], chess_move: &ChessMove) -> bool {
+        let Some((line, chess_move, _)) = self.legal_line(line, chess_move) else {return false};
+        let Some(node) = self.tree.node_mut(&line) else {return false};
+        let choices = node.choices_mut();
+        let len = choices.len();
+        choices.retain(|m| *m != chess_move);
+        choices.len() != len
+    }
+
+    pub fn add_reply(&mut self, line: &[ChessMove], chess_move: &ChessMove) -> bool {
+        let Some((line, chess_move, turn)) = self.legal_line(line, chess_move) else {return false};
+        if turn == self.color {return false}
+        self.tree.add_line(&line);
+        self.tree.node_mut(&line).unwrap().add_move(&chess_move);
+        true
+    }
+
+    // Returns the moves to consider from the end of `line`: our choices on our
+    // turns, every known reply on the opponent's.
+    pub fn moves(&self, line: &[ChessMove]) -> Vec<ChessMove> {
+        match self.tree.node(line) {
+            Some(node) if self.is_our_turn(node) => node.choices().to_vec(),
+            Some(node) => node.children_by_popularity().into_iter().map(|(m, _)| *m).collect(),
+            None => vec![]
+        }
+    }
+
+    // Lists the opponent moves played in at least `min_share` (0.05 for 5%) of the
+    // database games reaching a repertoire position that we have no answer to,
+    // most frequent first. The frequency only discounts the opponent's moves since
+    // ours are always the chosen ones.
+    pub fn coverage_gaps(&self, database: &OpeningTree, min_share: f64) -> Vec<CoverageGap> {
+        let mut gaps = vec![];
+        if database.position().hash() == self.tree.position().hash() {
+            self.collect_gaps(&self.tree, database, 1., min_share, &mut vec![], &mut gaps);
+        }
+        gaps.sort_by(|g1, g2| g2.frequency.total_cmp(&g1.frequency).then(g2.games.cmp(&g1.games)));
+        gaps
+    }
+
+    fn collect_gaps(&self, node: &OpeningTree, database: &OpeningTree, reach: f64, min_share: f64,
+                    line: &mut Vec<ChessMove>, gaps: &mut Vec<CoverageGap>) {
+        if self.is_our_turn(node) {
+            for chess_move in node.choices() {
+                if let (Some(child), Some(db_child)) = (node.child(chess_move), database.child(chess_move)) {
+                    line.push(*chess_move);
+                    self.collect_gaps(child, db_child, reach, min_share, line, gaps);
+                    line.pop();
+                }
+            }
+            return;
+        }
+        let total = database.children().map(|(_, child)| child.stats().games).sum::<usize>();
+        if total == 0 {return}
+        for (chess_move, db_child) in database.children_by_popularity() {
+            let games = db_child.stats().games;
+            let share = games as f64 / total as f64;
+            if share < min_share {break}
+            line.push(*chess_move);
+            match node.child(chess_move) {
+                Some(child) if !child.choices().is_empty() =>
+                    self.collect_gaps(child, db_child, reach * share, min_share, line, gaps),
+                _ => gaps.push(CoverageGap {
+                    line: line.clone(),
+                    games,
+                    share,
+                    frequency: reach * share,
+                    notation: writer::write_line(line, self.tree.position())
+                })
+            }
+            line.pop();
+        }
+    }
+
+    pub fn validate(&self) -> Vec<RepertoireIssue> {
+        let mut issues = vec![];
+        self.validate_node(&self.tree, &mut vec![], &mut issues);
+        issues
+    }
+
+    fn validate_node(&self, node: &OpeningTree, line: &mut Vec<ChessMove>, issues: &mut Vec<RepertoireIssue>) {
+        let kind = match (self.is_our_turn(node), node.choices().is_empty()) {
+            (true, true) => Some(RepertoireIssueKind::MissingChoice),
+            (false, false) => Some(RepertoireIssueKind::ChoiceOnOpponentTurn),
+            _ => None
+        };
+        if let Some(kind) = kind {
+            let notation = writer::write_line(line, self.tree.position());
+            issues.push(RepertoireIssue {kind, line: line.clone(), notation});
+        }
+        for chess_move in self.moves(line) {
+            if let Some(child) = node.child(&chess_move) {
+                line.push(chess_move);
+                self.validate_node(child, line, issues);
+                line.pop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mv(board: &Board, start: &[u8], end: &[u8]) -> ChessMove {
+        let start = Coord::from_str(start).unwrap();
+        let end = Coord::from_str(end).unwrap();
+        ChessMove::new(board.get(&start).unwrap(), start, end)
+    }
+
+    fn san(board: &Board, notation: &[u8]) -> ChessMove {
+        ChessMove::from_notation(notation, board).unwrap()
+    }
+
+    #[test]
+    fn hand_built_moves_match_parsed_moves() {
+        let mut repertoire = Repertoire::from_pgn(WHITE, "1. e4 e5 2. Nf3 Nc6 3. Bb5 *").unwrap();
+        let tree = repertoire.tree();
+        let e4 = san(tree.position(), b"e4");
+        let mut board = *tree.position();
+        board.do_move(&e4);
+        let hand_e5 = mv(&board, b"e7", b"e5");
+        let mut after_e5 = board;
+        after_e5.do_move(&hand_e5);
+
+        assert!(!repertoire.add_choice(&[e4], &hand_e5));
+        assert!(repertoire.add_reply(&[e4], &hand_e5));
+        assert_eq!(repertoire.tree().node(&[e4]).unwrap().children().count(), 1);
+
+        let hand_nf3 = mv(&after_e5, b"g1", b"f3");
+        assert!(repertoire.add_choice(&[e4, hand_e5], &hand_nf3));
+        assert_eq!(repertoire.choices(&[e4, san(&board, b"e5")]).len(), 1);
+    }
+
+    #[test]
+    fn check_and_capture_flags_are_filled_in() {
+        let pgn = "1. e4 d5 2. exd5 Qxd5 3. Nc3 Qa5 4. d4 c6 5. Nf3 Bg4 6. Bc4 e6 7. h3 Bh5 *";
+        let mut repertoire = Repertoire::from_pgn(WHITE, pgn).unwrap();
+        let moves = Game::from_pgn(pgn).unwrap().moves();
+        let mut board = *repertoire.tree().position();
+        for m in &moves[..2] {
+            board.do_move(m);
+        }
+        let hand_exd5 = mv(&board, b"e4", b"d5");
+        assert!(repertoire.add_choice(&moves[..2], &hand_exd5));
+        let node = repertoire.tree().node(&moves[..2]).unwrap();
+        assert_eq!(node.children().count(), 1);
+        assert_eq!(node.choices(), &[moves[2]]);
+    }
+
+    #[test]
+    fn illegal_moves_are_rejected() {
+        let mut repertoire = Repertoire::new(WHITE);
+        let board = Board::default();
+        let e5 = ChessMove::new(board.get(&Coord::from_str(b"e2").unwrap()).unwrap(),
+            Coord::from_str(b"e2").unwrap(), Coord::from_str(b"e5").unwrap());
+        assert!(!repertoire.add_choice(&[], &e5));
+        assert!(repertoire.tree().is_leaf());
+        let e4 = san(&board, b"e4");
+        assert!(!repertoire.add_reply(&[], &e4));
+        assert!(repertoire.add_choice(&[], &e4));
+        assert_eq!(repertoire.validate(), vec![]);
+    }
+
+    #[test]
+    fn validate_reports_missing_choices() {
+        let repertoire = Repertoire::from_pgn(BLACK, "1. e4 (1. d4) c5 *").unwrap();
+        let issues = repertoire.validate();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].kind, RepertoireIssueKind::MissingChoice);
+        assert_eq!(issues[0].to_string(), "no move chosen after 1. d4");
+    }
+
+    fn database() -> OpeningTree {
+        let lines = [("1. e4 e5 2. Nf3 Nc6", 8), ("1. e4 e5 2. Nf3 d6", 2), ("1. e4 c5", 6), ("1. e4 e6", 3),
+            ("1. e4 d5", 1), ("1. d4 d5", 5)];
+        let pgn: String = lines.iter()
+            .flat_map(|(line, count)| std::iter::repeat_n(format!("[Event \"?\"]\n\n{} *\n\n", line), *count))
+            .collect();
+        OpeningTree::from_games(Parser::from_reader(pgn.as_bytes()).map(|game| game.unwrap()), 10)
+    }
+
+    #[test]
+    fn coverage_gaps_by_frequency() {
+        let repertoire = Repertoire::from_pgn(WHITE, "1. e4 e5 (1... c5) 2. Nf3 Nc6 3. Bb5 *").unwrap();
+        let gaps = repertoire.coverage_gaps(&database(), 0.1);
+        let found: Vec<String> = gaps.iter().map(|gap| gap.to_string()).collect();
+        assert_eq!(found, vec![
+            "1. e4 c5: 30.0% of games here, 30.0% overall (6 games)",
+            "1. e4 e6: 15.0% of games here, 15.0% overall (3 games)",
+            "1. e4 e5 2. Nf3 d6: 20.0% of games here, 10.0% overall (2 games)"
+        ]);
+        assert_eq!(gaps[2].line.len(), 4);
+    }
+
+    #[test]
+    fn coverage_gaps_below_min_share_are_skipped() {
+        let repertoire = Repertoire::from_pgn(WHITE, "1. e4 e5 (1... c5 2. Nf3) (1... e6 2. d4) 2. Nf3 Nc6 (2... d6 3. d4) 3. Bb5 *").unwrap();
+        assert!(repertoire.coverage_gaps(&database(), 0.1).is_empty());
+        let gaps = repertoire.coverage_gaps(&database(), 0.05);
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].games, 1);
+        assert_eq!(gaps[0].to_string(), "1. e4 d5: 5.0% of games here, 5.0% overall (1 games)");
+        let other_root = Board::try_from_fen(b"8/8/8/8/8/8/8/k6K w - - 0 1").unwrap();
+        assert!(repertoire.coverage_gaps(&OpeningTree::new_from_position(&other_root), 0.).is_empty());
+    }
+}
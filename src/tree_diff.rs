@@ -0,0 +1,279 @@
+use crate::*;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeChangeKind {
+    AddedLine,
+    RemovedLine,
+    ChoicesChanged(Vec<ChessMove>, Vec<ChessMove>),
+    CommentChanged(Option<String>, Option<String>),
+    NagsChanged(Vec<u8>, Vec<u8>)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeChange {
+    pub line: Vec<ChessMove>,
+    pub kind: TreeChangeKind,
+    description: String
+}
+
+impl fmt::Display for TreeChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.description)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictKind {
+    Choices,
+    Comment,
+    Nags,
+    Stats,
+    RemovedAndModified
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflict {
+    pub line: Vec<ChessMove>,
+    pub kind: ConflictKind,
+    description: String
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.description)
+    }
+}
+
+#[derive(Debug)]
+pub struct MergeResult {
+    pub tree: OpeningTree,
+    pub conflicts: Vec<MergeConflict>
+}
+
+fn line_notation(line: &[ChessMove], root: &Board) -> String {
+    if line.is_empty() {String::from("initial position")} else {writer::write_line(line, root)}
+}
+
+fn moves_notation(moves: &[ChessMove], board: &Board) -> String {
+    if moves.is_empty() {return String::from("none")}
+    moves.iter().map(|m| String::from_utf8_lossy(&m.notation(board)).into_owned()).collect::<Vec<_>>().join(", ")
+}
+
+// Only used for stable diff output; merged trees keep their insertion order.
+fn sorted_children(node: &OpeningTree) -> Vec<(&ChessMove, &OpeningTree)> {
+    let mut children: Vec<(&ChessMove, &OpeningTree)> = node.children().collect();
+    children.sort_by_key(|(m, _)| m.uci());
+    children
+}
+
+fn same_subtree(t1: &OpeningTree, t2: &OpeningTree) -> bool {
+    t1.stats() == t2.stats() && t1.choices() == t2.choices() && t1.comment() == t2.comment() && t1.nags() == t2.nags()
+        && t1.children().count() == t2.children().count()
+        && t1.children().all(|(m, c1)| t2.child(m).is_some_and(|c2| same_subtree(c1, c2)))
+}
+
+impl OpeningTree {
+    // Changes going from `self` to `other`, as move sequences from the root. An
+    // added or removed line is reported once, at the first move that differs.
+    pub fn diff(&self, other: &OpeningTree) -> Vec<TreeChange> {
+        let mut changes = vec![];
+        diff_nodes(self, other, self.position(), &mut vec![], &mut changes);
+        changes
+    }
+
+    // Three-way merge of two edited versions of `base`. When both sides change
+    // the same thing differently ours is kept and the conflict is reported.
+    pub fn merge3(base: &OpeningTree, ours: &OpeningTree, theirs: &OpeningTree) -> MergeResult {
+        let mut conflicts = vec![];
+        let tree = merge_nodes(Some(base), ours, theirs, ours.position(), &mut vec![], &mut conflicts);
+        MergeResult {tree, conflicts}
+    }
+}
+
+fn diff_nodes(old: &OpeningTree, new: &OpeningTree, root: &Board, line: &mut Vec<ChessMove>, changes: &mut Vec<TreeChange>) {
+    let at = line_notation(line, root);
+    let mut change = |kind: TreeChangeKind, description: String| changes.push(TreeChange {line: line.clone(), kind, description});
+    if old.choices() != new.choices() {
+        let description = format!("~ choices after {}: {} -> {}", at,
+            moves_notation(old.choices(), old.position()), moves_notation(new.choices(), new.position()));
+        change(TreeChangeKind::ChoicesChanged(old.choices().to_vec(), new.choices().to_vec()), description);
+    }
+    if old.comment() != new.comment() {
+        let description = format!("~ comment at {}: {:?} -> {:?}", at, old.comment().unwrap_or(""), new.comment().unwrap_or(""));
+        change(TreeChangeKind::CommentChanged(old.comment().map(String::from), new.comment().map(String::from)), description);
+    }
+    if old.nags() != new.nags() {
+        let description = format!("~ NAGs at {}: {:?} -> {:?}", at, old.nags(), new.nags());
+        change(TreeChangeKind::NagsChanged(old.nags().to_vec(), new.nags().to_vec()), description);
+    }
+    for (chess_move, old_child) in sorted_children(old) {
+        line.push(*chess_move);
+        match new.child(chess_move) {
+            Some(new_child) => diff_nodes(old_child, new_child, root, line, changes),
+            None => changes.push(TreeChange {
+                line: line.clone(),
+                kind: TreeChangeKind::RemovedLine,
+                description: format!("- {}", line_notation(line, root))
+            })
+        }
+        line.pop();
+    }
+    for (chess_move, _) in sorted_children(new) {
+        if old.child(chess_move).is_none() {
+            line.push(*chess_move);
+            changes.push(TreeChange {
+                line: line.clone(),
+                kind: TreeChangeKind::AddedLine,
+                description: format!("+ {}", line_notation(line, root))
+            });
+            line.pop();
+        }
+    }
+}
+
+fn merge_value<T: PartialEq + Clone>(base: Option<&T>, ours: &T, theirs: &T) -> (T, bool) {
+    if ours == theirs || base == Some(theirs) {
+        (ours.clone(), false)
+    }
+    else if base == Some(ours) {
+        (theirs.clone(), false)
+    }
+    else {
+        (ours.clone(), true)
+    }
+}
+
+fn merge_nodes(base: Option<&OpeningTree>, ours: &OpeningTree, theirs: &OpeningTree, root: &Board,
+               line: &mut Vec<ChessMove>, conflicts: &mut Vec<MergeConflict>) -> OpeningTree {
+    let mut tree = OpeningTree::new_from_position(ours.position());
+    let mut conflict = |kind: ConflictKind, what: &str| conflicts.push(MergeConflict {
+        line: line.clone(),
+        kind,
+        description: format!("conflicting {} at {}, kept ours", what, line_notation(line, root))
+    });
+
+    let (stats, is_conflict) = merge_value(base.map(|b| b.stats()), ours.stats(), theirs.stats());
+    if is_conflict {conflict(ConflictKind::Stats, "statistics")}
+    tree.set_stats(stats);
+    let base_comment = base.map(|b| b.comment());
+    let (comment, is_conflict) = merge_value(base_comment.as_ref(), &ours.comment(), &theirs.comment());
+    if is_conflict {conflict(ConflictKind::Comment, "comments")}
+    tree.set_comment(comment.map(String::from));
+    let (nags, is_conflict) = merge_value(base.map(|b| b.nags()).as_ref(), &ours.nags(), &theirs.nags());
+    if is_conflict {conflict(ConflictKind::Nags, "NAGs")}
+    tree.set_nags(nags.to_vec());
+    let (choices, is_conflict) = merge_value(base.map(|b| b.choices()).as_ref(), &ours.choices(), &theirs.choices());
+    if is_conflict {conflict(ConflictKind::Choices, "choices")}
+    let choices = choices.to_vec();
+
+    let mut moves: Vec<ChessMove> = vec![];
+    for node in [Some(ours), Some(theirs), base].into_iter().flatten() {
+        for (chess_move, _) in node.children() {
+            if !moves.contains(chess_move) {moves.push(*chess_move)}
+        }
+    }
+    for chess_move in moves {
+        line.push(chess_move);
+        let base_child = base.and_then(|b| b.child(&chess_move));
+        let child = match (base_child, ours.child(&chess_move), theirs.child(&chess_move)) {
+            (_, Some(our_child), Some(their_child)) => Some(merge_nodes(base_child, our_child, their_child, root, line, conflicts)),
+            (None, Some(child), None) | (None, None, Some(child)) => Some(child.clone()),
+            (Some(base_child), Some(child), None) | (Some(base_child), None, Some(child)) => {
+                if same_subtree(base_child, child) {
+                    None
+                }
+                else {
+                    conflicts.push(MergeConflict {
+                        line: line.clone(),
+                        kind: ConflictKind::RemovedAndModified,
+                        description: format!("{} removed on one side and modified on the other, kept", line_notation(line, root))
+                    });
+                    Some(child.clone())
+                }
+            }
+            _ => None
+        };
+        line.pop();
+        if let Some(child) = child {
+            tree.insert_child(chess_move, child);
+        }
+    }
+    for chess_move in choices {
+        if tree.child(&chess_move).is_some() {
+            tree.choices_mut().push(chess_move);
+        }
+    }
+    tree
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(pgn: &str) -> OpeningTree {
+        Repertoire::from_pgn(WHITE, pgn).unwrap().into_tree()
+    }
+
+    fn descriptions<T: ToString>(items: &[T]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    fn movetext(tree: &OpeningTree) -> String {
+        let pgn = tree.to_pgn().replace('\n', " ");
+        pgn[pgn.find("1.").unwrap()..].to_string()
+    }
+
+    #[test]
+    fn diff_reports_lines_choices_and_comments() {
+        let old = tree("1. e4 e5 2. Nf3 *");
+        let new = tree("1. e4 {Best} e5 (1... c5) 2. Bc4 *");
+        let changes = old.diff(&new);
+        assert_eq!(descriptions(&changes), vec![
+            "~ comment at 1. e4: \"\" -> \"Best\"",
+            "~ choices after 1. e4 e5: Nf3 -> Bc4",
+            "- 1. e4 e5 2. Nf3",
+            "+ 1. e4 e5 2. Bc4",
+            "+ 1. e4 c5"
+        ]);
+        assert!(matches!(&changes[0].kind, TreeChangeKind::CommentChanged(None, Some(comment)) if comment == "Best"));
+        assert_eq!(changes[2].kind, TreeChangeKind::RemovedLine);
+        assert_eq!(changes[4].kind, TreeChangeKind::AddedLine);
+        assert_eq!(changes[4].line.len(), 2);
+        assert!(old.diff(&old).is_empty());
+    }
+
+    #[test]
+    fn merge_keeps_the_order_of_ours() {
+        let base = tree("1. e4 e5 (1... c5) *");
+        let merged = OpeningTree::merge3(&base, &base, &base);
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(movetext(&merged.tree).trim_end(), "1. e4 e5 (1... c5) *");
+    }
+
+    #[test]
+    fn merge_combines_edits_from_both_sides() {
+        let base = tree("1. e4 e5 2. Nf3 *");
+        let ours = tree("1. e4 e5 (1... c5 2. Nf3) 2. Nf3 *");
+        let theirs = tree("1. e4 {Main} e5 (1... e6 2. d4) 2. Nf3 *");
+        let merged = OpeningTree::merge3(&base, &ours, &theirs);
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(movetext(&merged.tree).trim_end(), "1. e4 {Main} 1... e5 (1... c5 2. Nf3) (1... e6 2. d4) 2. Nf3 *");
+    }
+
+    #[test]
+    fn merge_reports_conflicts() {
+        let base = tree("1. e4 e5 2. Nf3 (2. d4) *");
+        let ours = tree("1. e4 {Ours} e5 2. Nf3 *");
+        let theirs = tree("1. e4 {Theirs} e5 2. d4 (2. Nf3) 2... d5 *");
+        let merged = OpeningTree::merge3(&base, &ours, &theirs);
+        assert_eq!(descriptions(&merged.conflicts), vec![
+            "conflicting comments at 1. e4, kept ours",
+            "conflicting choices at 1. e4 e5, kept ours",
+            "1. e4 e5 2. d4 removed on one side and modified on the other, kept"
+        ]);
+        let kinds: Vec<ConflictKind> = merged.conflicts.iter().map(|c| c.kind).collect();
+        assert_eq!(kinds, vec![ConflictKind::Comment, ConflictKind::Choices, ConflictKind::RemovedAndModified]);
+        assert_eq!(movetext(&merged.tree).trim_end(), "1. e4 {Ours} 1... e5 2. Nf3 (2. d4 d5) *");
+    }
+}
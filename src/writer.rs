@@ -0,0 +1,193 @@
+use crate::*;
+use std::io::{self, Write};
+
+const LINE_LENGTH: usize = 80;
+
+pub struct PgnWriter<W: Write> {
+    writer: W
+}
+
+impl<W: Write> PgnWriter<W> {
+    pub fn new(writer: W) -> Self {
+        PgnWriter {writer}
+    }
+
+    pub fn write_game(&mut self, game: &Game) -> io::Result<()> {
+        writeln!(self.writer, "{}", game.to_pgn())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn result_token(result: Option<Result>) -> &'static str {
+    match result {
+        Some(Result::White) => "1-0",
+        Some(Result::Black) => "0-1",
+        Some(Result::Draw) => "1/2-1/2",
+        None => "*"
+    }
+}
+
+pub(crate) fn write_headers(game: &Game) -> String {
+    let mut res = String::new();
+    for name in SEVEN_TAG_ROSTER {
+        let value = match name {
+            "Result" => result_token(game.result()).to_string(),
+            "Date" => game.headers().get(name).unwrap_or("????.??.??").to_string(),
+            _ => game.headers().get(name).unwrap_or("?").to_string()
+        };
+        res.push_str(&format!("[{} \"{}\"]\n", name, escape(&value)));
+    }
+    for (name, value) in game.headers().iter() {
+        if !SEVEN_TAG_ROSTER.contains(&name) {
+            res.push_str(&format!("[{} \"{}\"]\n", name, escape(value)));
+        }
+    }
+    res
+}
+
+fn push_comment(tokens: &mut Vec<String>, comment: &str) {
+    let comment = comment.replace('}', ")");
+    let words: Vec<&str> = comment.split_whitespace().collect();
+    match words[..] {
+        [] => tokens.push(String::from("{}")),
+        [word] => tokens.push(format!("{{{}}}", word)),
+        [first, .., last] => {
+            tokens.push(format!("{{{}", first));
+            tokens.extend(words[1..words.len()-1].iter().map(|word| word.to_string()));
+            tokens.push(format!("{}}}", last));
+        }
+    }
+}
+
+fn write_variation(variation: &Variation, board: &Board, tokens: &mut Vec<String>) {
+    let mut board = *board;
+    let mut needs_number = true;
+    if let Some(comment) = &variation.comment {
+        push_comment(tokens, comment);
+    }
+    for node in &variation.nodes {
+        let san = String::from_utf8_lossy(&node.chess_move.notation(&board)).into_owned();
+        tokens.push(match board.turn() {
+            Color::WHITE => format!("{}. {}", board.move_count(), san),
+            Color::BLACK if needs_number => format!("{}... {}", board.move_count(), san),
+            Color::BLACK => san
+        });
+        needs_number = false;
+        for nag in &node.nags {
+            tokens.push(format!("${}", nag));
+        }
+        if let Some(comment) = &node.comment {
+            push_comment(tokens, comment);
+            needs_number = true;
+        }
+        for sideline in &node.variations {
+            tokens.push(String::from("("));
+            write_variation(sideline, &board, tokens);
+            tokens.push(String::from(")"));
+            needs_number = true;
+        }
+        board.do_move(&node.chess_move);
+    }
+}
+
+fn wrap(tokens: &[String]) -> String {
+    let mut words = Vec::<String>::new();
+    let mut glue_next = false;
+    for token in tokens {
+        match words.last_mut() {
+            Some(word) if glue_next || token == ")" => word.push_str(token),
+            _ => words.push(token.clone())
+        }
+        glue_next = token == "(";
+    }
+    let mut res = String::new();
+    let mut line = String::new();
+    for word in words {
+        if !line.is_empty() && line.len() + 1 + word.len() > LINE_LENGTH {
+            res.push_str(&line);
+            res.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&word);
+    }
+    res.push_str(&line);
+    res.push('\n');
+    res
+}
+
+pub(crate) fn write_movetext(game: &Game) -> String {
+    let mut tokens = Vec::<String>::new();
+    write_variation(game.mainline(), &game.initial_board(), &mut tokens);
+    tokens.push(result_token(game.result()).to_string());
+    wrap(&tokens)
+}
+
+pub(crate) fn write_line(moves: &[ChessMove], board: &Board) -> String {
+    let mut tokens = Vec::<String>::new();
+    let variation = Variation {comment: None, nodes: moves.iter().map(|m| MoveNode::new(*m)).collect()};
+    write_variation(&variation, board, &mut tokens);
+    tokens.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANNOTATED: &str = "[Event \"Casual game\"]
+[Site \"?\"]
+[Date \"1895.??.??\"]
+[Round \"?\"]
+[White \"Steinitz\"]
+[Black \"Lasker\"]
+[Result \"1-0\"]
+[WhiteElo \"2650\"]
+
+{Opening} 1. e4 e5 $1 2. Nf3 (2. f4 {King's Gambit} 2... exf4 (2... Bc5) 3. Nf3) 2... Nc6
+3. Bb5 a6 4. Ba4 Nf6 5. O-O Be7 6. Re1 b5 7. Bb3 d6 8. c3 O-O 9. h3 Nb8 10. d4
+Nbd7 11. Nbd2 Bb7 12. Bc2 Re8 13. Nf1 Bf8 14. Ng3 g6 15. a4 c5 16. d5 c4 17. Bg5
+h6 18. Be3 Nc5 19. Qd2 h5 20. Bg5 Be7 21. Ra3 Nfd7 22. Bh6 {Threatening Bxf8} 1-0
+";
+
+    #[test]
+    fn export_format() {
+        let game = Game::from_pgn(ANNOTATED).unwrap();
+        let pgn = game.to_pgn();
+        assert!(pgn.starts_with("[Event \"Casual game\"]\n"));
+        assert!(pgn.replace('\n', " ").contains("1. e4 e5 $1 2. Nf3 (2. f4 {King's Gambit} 2... exf4 (2... Bc5) 3. Nf3) 2... Nc6"));
+        assert!(pgn.lines().all(|line| line.len() <= LINE_LENGTH));
+        assert!(pgn.lines().all(|line| !line.ends_with('.')));
+    }
+
+    #[test]
+    fn pgn_round_trip() {
+        let game = Game::from_pgn(ANNOTATED).unwrap();
+        let pgn = game.to_pgn();
+        let reparsed = Game::from_pgn(&pgn).unwrap();
+        assert_eq!(reparsed.headers(), game.headers());
+        assert_eq!(reparsed.mainline(), game.mainline());
+        assert_eq!(reparsed.result(), game.result());
+        assert_eq!(reparsed.to_pgn(), pgn);
+    }
+
+    #[test]
+    fn pgn_round_trip_from_position() {
+        let board = Board::try_from_fen(b"4k3/8/8/8/8/8/4P3/4K3 b - - 0 40").unwrap();
+        let mut game = Game::from_position(&board);
+        let chess_move = ChessMove::from_notation(b"Kd7", &board).unwrap();
+        game.mainline.nodes.push(MoveNode::new(chess_move));
+        let reparsed = Game::from_pgn(&game.to_pgn()).unwrap();
+        assert!(game.to_pgn().contains("40... Kd7"));
+        assert_eq!(reparsed.initial_board().to_fen(), board.to_fen());
+        assert_eq!(reparsed.moves(), game.moves());
+    }
+}
@@ -0,0 +1,47 @@
+use crate::*;
+
+const fn split_mix(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    (state, z ^ (z >> 31))
+}
+
+const fn generate_keys<const N: usize>(seed: u64) -> [u64; N] {
+    let mut keys = [0; N];
+    let mut state = seed;
+    let mut i = 0;
+    while i < N {
+        let (next_state, key) = split_mix(state);
+        state = next_state;
+        keys[i] = key;
+        i += 1;
+    }
+    keys
+}
+
+const PIECE_KEYS: [u64; 12*64] = generate_keys(0x436865737354726E);
+const CASTLING_KEYS: [u64; 4] = generate_keys(0x436173746C696E67);
+const EN_PASSANT_KEYS: [u64; 8] = generate_keys(0x456E50617373616E);
+const BLACK_TO_MOVE_KEY: u64 = split_mix(0x426C61636B).1;
+
+pub fn piece_key(piece: &Piece, coord: &Coord) -> u64 {
+    let piece_index = piece.piece_type as usize + if piece.color == BLACK {6} else {0};
+    PIECE_KEYS[piece_index*64 + (coord.0*8 + coord.1) as usize]
+}
+
+pub fn castling_key(castlings: &[bool; 4]) -> u64 {
+    castlings.iter().zip(CASTLING_KEYS).fold(0, |acc, (allowed, key)| if *allowed {acc ^ key} else {acc})
+}
+
+pub fn en_passant_key(coord: &Coord) -> u64 {
+    EN_PASSANT_KEYS[coord.1 as usize]
+}
+
+pub fn turn_key(turn: &Color) -> u64 {
+    match turn {
+        Color::WHITE => 0,
+        Color::BLACK => BLACK_TO_MOVE_KEY
+    }
+}